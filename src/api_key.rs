@@ -0,0 +1,426 @@
+//! Provider API key lookup: from a command's output (`api_key_commands`), a
+//! secrets file (`api_key_files`) or an environment variable, in that order,
+//! or from the list of sources in `api_keys`. Keys are looked up when a
+//! provider first needs one, so building a provider never runs a command,
+//! and are cached for the life of the process, together with where they
+//! came from, so that -v can report it.
+//!
+//! With several keys, the first one that is not cooling down is used. A key
+//! that hits a rate limit or quota error cools down for the time the provider
+//! asks for, and the request is sent again with the next key (see
+//! `HttpClient::execute`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::error::CrabError;
+
+/// Where a provider's API key came from.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    Command(String),
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Command(command) => write!(f, "command `{command}`"),
+            KeySource::File(path) => write!(f, "file {}", path.display()),
+            KeySource::Env(var) => write!(f, "environment variable {var}"),
+        }
+    }
+}
+
+/// Where to look for one key. The first source that is set is used.
+#[derive(Debug, Clone)]
+pub struct Lookup {
+    pub command: Option<String>,
+    pub file: Option<PathBuf>,
+    pub var: Option<String>,
+}
+
+/// A provider's API key sources, looked up on the first `active` call.
+#[derive(Debug)]
+pub struct Keys {
+    provider: String,
+    lookups: Vec<Lookup>,
+    resolved: OnceLock<Vec<String>>,
+}
+
+impl Keys {
+    pub fn new(provider: &str, lookups: Vec<Lookup>) -> Self {
+        Self {
+            provider: provider.to_string(),
+            lookups,
+            resolved: OnceLock::new(),
+        }
+    }
+
+    /// Keys that are known up front and never looked up.
+    pub fn fixed(provider: &str, keys: Vec<String>) -> Self {
+        Self {
+            provider: provider.to_string(),
+            lookups: Vec::new(),
+            resolved: OnceLock::from(keys),
+        }
+    }
+
+    /// The key to use next (see `active`), looking the keys up first if
+    /// needed. None if no source yields a key.
+    pub fn active(&self) -> Result<Option<&str>, CrabError> {
+        let keys = match self.resolved.get() {
+            Some(keys) => keys,
+            None => {
+                let keys = resolve(&self.provider, &self.lookups)?;
+                self.resolved.get_or_init(|| keys)
+            }
+        };
+        Ok(active(keys))
+    }
+
+    /// Whether any source may yield a key, without running commands or
+    /// reading files: a command or file is configured, or a variable is set.
+    pub fn is_configured(&self) -> bool {
+        self.resolved.get().is_some_and(|keys| !keys.is_empty())
+            || self.lookups.iter().any(|lookup| {
+                lookup.command.is_some()
+                    || lookup.file.is_some()
+                    || lookup
+                        .var
+                        .as_ref()
+                        .is_some_and(|var| std::env::var_os(var).is_some())
+            })
+    }
+}
+
+/// A key, its source and its label for -v.
+#[derive(Debug, Clone)]
+struct Key {
+    key: String,
+    source: KeySource,
+    label: String,
+}
+
+fn cache() -> &'static Mutex<BTreeMap<String, Vec<Key>>> {
+    static CACHE: OnceLock<Mutex<BTreeMap<String, Vec<Key>>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Until when each key cools down after a rate limit or quota error.
+fn cooldowns() -> &'static Mutex<HashMap<String, Instant>> {
+    static COOLDOWNS: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+    COOLDOWNS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Key rotations so far, for -v.
+fn rotation_log() -> &'static Mutex<Vec<String>> {
+    static LOG: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+    LOG.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Key sources that failed while others worked, by provider, for -v.
+fn skipped_log() -> &'static Mutex<Vec<(String, String)>> {
+    static LOG: OnceLock<Mutex<Vec<(String, String)>>> = OnceLock::new();
+    LOG.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Returns the keys of `provider`, in order. Empty if no source is
+/// configured and no environment variable is set.
+///
+/// A failing command or an unsafe key file is an error rather than a
+/// fallback to the environment variable. With several sources, one that
+/// fails is skipped (and reported by `skipped`) as long as another yields a
+/// key; if none does, the first failure is returned.
+fn resolve(provider: &str, lookups: &[Lookup]) -> Result<Vec<String>, CrabError> {
+    if let Some(keys) = cache().lock().ok().and_then(|c| c.get(provider).cloned()) {
+        return Ok(keys.into_iter().map(|k| k.key).collect());
+    }
+
+    let mut keys = Vec::new();
+    let mut failures = Vec::new();
+    for (index, lookup) in lookups.iter().enumerate() {
+        let label = label(provider, index, lookups.len());
+        match lookup_key(provider, lookup) {
+            Ok(Some((key, source))) => keys.push(Key { key, source, label }),
+            Ok(None) => {}
+            Err(e) => failures.push((label, e)),
+        }
+    }
+
+    if keys.is_empty() {
+        if let Some((_, e)) = failures.into_iter().next() {
+            return Err(e);
+        }
+        return Ok(Vec::new());
+    }
+    if let Ok(mut log) = skipped_log().lock() {
+        for (label, e) in failures {
+            log.push((provider.to_string(), format!("{label} skipped: {e}")));
+        }
+    }
+    if let Ok(mut cache) = cache().lock() {
+        cache.insert(provider.to_string(), keys.clone());
+    }
+    Ok(keys.into_iter().map(|k| k.key).collect())
+}
+
+/// Looks up one key: from the command, else the file, else the variable.
+fn lookup_key(provider: &str, lookup: &Lookup) -> Result<Option<(String, KeySource)>, CrabError> {
+    if let Some(command) = &lookup.command {
+        let key = run_command(provider, command)?;
+        return Ok(Some((key, KeySource::Command(command.clone()))));
+    }
+    if let Some(path) = &lookup.file {
+        return Ok(Some((
+            read_file(provider, path)?,
+            KeySource::File(path.clone()),
+        )));
+    }
+    Ok(lookup
+        .var
+        .as_ref()
+        .and_then(|var| Some((std::env::var(var).ok()?, KeySource::Env(var.clone())))))
+}
+
+/// The sources of the keys looked up so far, of `provider` or of every
+/// provider, labeled by provider (and position, for providers with several
+/// keys).
+pub fn sources(provider: Option<&str>) -> Vec<(String, KeySource)> {
+    let Ok(cache) = cache().lock() else {
+        return Vec::new();
+    };
+    cache
+        .iter()
+        .filter(|(name, _)| provider.is_none_or(|p| p == name.as_str()))
+        .flat_map(|(_, keys)| keys.iter().map(|k| (k.label.clone(), k.source.clone())))
+        .collect()
+}
+
+/// The key sources that were skipped because they failed, of `provider` or
+/// of every provider, with the reason.
+pub fn skipped(provider: Option<&str>) -> Vec<String> {
+    let Ok(log) = skipped_log().lock() else {
+        return Vec::new();
+    };
+    log.iter()
+        .filter(|(name, _)| provider.is_none_or(|p| p == name))
+        .map(|(_, message)| message.clone())
+        .collect()
+}
+
+/// The key to use next: the first one that is not cooling down, or the one
+/// whose cooldown ends first if they all are.
+fn active(keys: &[String]) -> Option<&str> {
+    let Ok(cooldowns) = cooldowns().lock() else {
+        return keys.first().map(String::as_str);
+    };
+    let now = Instant::now();
+    keys.iter()
+        .find(|key| cooldowns.get(*key).is_none_or(|until| *until <= now))
+        .or_else(|| keys.iter().min_by_key(|key| cooldowns.get(*key)))
+        .map(String::as_str)
+}
+
+/// Lets `key` of `provider` cool down for `cooldown` and returns another key
+/// that is not cooling down, if there is one. The switch is logged for -v.
+pub fn rotate(provider: &str, key: &str, cooldown: Duration) -> Option<String> {
+    let keys = cache().lock().ok()?.get(provider).cloned()?;
+    let current = keys.iter().find(|k| k.key == key)?;
+
+    let now = Instant::now();
+    let next = {
+        let mut cooldowns = cooldowns().lock().ok()?;
+        cooldowns.insert(key.to_string(), now + cooldown);
+        keys.iter()
+            .find(|k| cooldowns.get(&k.key).is_none_or(|until| *until <= now))
+            .cloned()
+    }?;
+
+    if let Ok(mut log) = rotation_log().lock() {
+        log.push(format!(
+            "{} ({}) cooling down for {}s; switched to {} ({})",
+            current.label,
+            current.source,
+            cooldown.as_secs(),
+            next.label,
+            next.source,
+        ));
+    }
+    Some(next.key)
+}
+
+/// The key rotations of this process, oldest first.
+pub fn rotations() -> Vec<String> {
+    rotation_log()
+        .lock()
+        .map(|log| log.clone())
+        .unwrap_or_default()
+}
+
+fn label(provider: &str, index: usize, count: usize) -> String {
+    if count > 1 {
+        format!("{provider} key {}", index + 1)
+    } else {
+        provider.to_string()
+    }
+}
+
+/// Runs `command` with the shell and returns its trimmed output.
+fn run_command(provider: &str, command: &str) -> Result<String, CrabError> {
+    #[cfg(unix)]
+    let output = Command::new("sh").arg("-c").arg(command).output();
+    #[cfg(not(unix))]
+    let output = Command::new("cmd").arg("/C").arg(command).output();
+
+    let output = output.map_err(|e| {
+        CrabError::ConfigError(format!(
+            "Cannot run the API key command for {provider} ({command}): {e}"
+        ))
+    })?;
+    if !output.status.success() {
+        let mut message = format!(
+            "API key command for {provider} ({command}) failed with {}",
+            output.status
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.trim().is_empty() {
+            message.push_str(&format!(": {}", stderr.trim()));
+        }
+        return Err(CrabError::ConfigError(message));
+    }
+    let key = String::from_utf8_lossy(&output.stdout);
+    non_empty(provider, key.trim(), &format!("command ({command})"))
+}
+
+/// Reads a key file, refusing files that group or others can read.
+fn read_file(provider: &str, path: &Path) -> Result<String, CrabError> {
+    let unreadable = |e: std::io::Error| {
+        CrabError::ConfigError(format!(
+            "Cannot read API key file {} for {provider}: {e}",
+            path.display()
+        ))
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path)
+            .map_err(unreadable)?
+            .permissions()
+            .mode();
+        if mode & 0o044 != 0 {
+            return Err(CrabError::ConfigError(format!(
+                "Refusing API key file {} for {provider}: it is readable by group or others \
+                 (mode {:o}); run chmod 600 on it",
+                path.display(),
+                mode & 0o777
+            )));
+        }
+    }
+    let contents = std::fs::read_to_string(path).map_err(unreadable)?;
+    non_empty(
+        provider,
+        contents.trim(),
+        &format!("file {}", path.display()),
+    )
+}
+
+fn non_empty(provider: &str, key: &str, source: &str) -> Result<String, CrabError> {
+    if key.is_empty() {
+        return Err(CrabError::ConfigError(format!(
+            "API key {source} for {provider} is empty"
+        )));
+    }
+    Ok(key.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `contents` to a fresh file in the temp directory with `mode`.
+    #[cfg(unix)]
+    fn key_file(name: &str, contents: &str, mode: u32) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("crabai-test-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_private_key_file() {
+        let path = key_file("private", "sk-file-key\n", 0o600);
+        let key = read_file("openai", &path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(key.unwrap(), "sk-file-key");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_key_file_readable_by_others() {
+        for (name, mode) in [("group", 0o640), ("world", 0o644)] {
+            let path = key_file(name, "sk-file-key", mode);
+            let result = read_file("openai", &path);
+            std::fs::remove_file(&path).unwrap();
+            match result {
+                Err(CrabError::ConfigError(message)) => {
+                    assert!(message.contains("chmod 600"), "{message}")
+                }
+                other => panic!("expected a permission error for mode {mode:o}, got {other:?}"),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_empty_key_file() {
+        let path = key_file("empty", " \n", 0o600);
+        let result = read_file("openai", &path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(CrabError::ConfigError(_))));
+    }
+
+    #[test]
+    fn missing_sources_are_not_configured() {
+        let lookups = vec![Lookup {
+            command: None,
+            file: None,
+            var: Some("CRABAI_TEST_UNSET_API_KEY".to_string()),
+        }];
+        let keys = Keys::new("test-unset", lookups);
+        assert!(!keys.is_configured());
+        assert_eq!(keys.active().unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_failing_source_when_no_key_is_found() {
+        let lookups = vec![Lookup {
+            command: Some("exit 1".to_string()),
+            file: None,
+            var: None,
+        }];
+        let keys = Keys::new("test-fail", lookups);
+        assert!(matches!(keys.active(), Err(CrabError::ConfigError(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_failing_source_when_another_has_a_key() {
+        let command = |command: &str| Lookup {
+            command: Some(command.to_string()),
+            file: None,
+            var: None,
+        };
+        let keys = Keys::new("test-skip", vec![command("exit 1"), command("echo sk-second")]);
+        assert_eq!(keys.active().unwrap(), Some("sk-second"));
+        assert_eq!(skipped(Some("test-skip")).len(), 1);
+    }
+}
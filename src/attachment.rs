@@ -0,0 +1,71 @@
+//! File attachments for -a/--attach: images and PDFs sent to multimodal
+//! models along with the prompt.
+
+use std::path::Path;
+
+use crate::error::CrabError;
+use crate::types::Attachment;
+
+/// Supported attachment formats by file extension, with their MIME types.
+const FORMATS: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("pdf", "application/pdf"),
+];
+
+/// Reads a file to attach. The MIME type is detected from the file's
+/// signature, falling back to its extension.
+pub fn load(path: &Path) -> Result<Attachment, CrabError> {
+    let data = std::fs::read(path).map_err(|e| {
+        CrabError::ConfigError(format!("Cannot read attachment {}: {e}", path.display()))
+    })?;
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let mime_type = sniff(&data)
+        .or_else(|| {
+            FORMATS
+                .iter()
+                .find(|(ext, _)| *ext == extension)
+                .map(|(_, mime)| *mime)
+        })
+        .ok_or_else(|| {
+            CrabError::ConfigError(format!(
+                "Unsupported attachment '{}'. Supported: PNG, JPEG, GIF, WebP images and PDF",
+                path.display()
+            ))
+        })?;
+
+    Ok(Attachment {
+        file_name: path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("attachment")
+            .to_string(),
+        mime_type: mime_type.to_string(),
+        data,
+    })
+}
+
+/// Detects a supported format from the file's leading bytes.
+fn sniff(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if data.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,56 @@
+//! Audio input for --audio: loads a recording for transcription. The
+//! transcript then takes the place of STDIN in the prompt.
+
+use std::path::Path;
+
+use crate::error::CrabError;
+use crate::types::TranscriptionRequest;
+
+/// Supported audio formats by file extension, with their MIME types.
+const FORMATS: &[(&str, &str)] = &[
+    ("mp3", "audio/mpeg"),
+    ("mpga", "audio/mpeg"),
+    ("mpeg", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("m4a", "audio/mp4"),
+    ("mp4", "audio/mp4"),
+    ("aac", "audio/aac"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/ogg"),
+    ("flac", "audio/flac"),
+    ("webm", "audio/webm"),
+];
+
+/// Reads an audio file into a transcription request for `model` (None for
+/// the provider's default). The format is taken from the file extension.
+pub fn load(path: &Path, model: Option<String>) -> Result<TranscriptionRequest, CrabError> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let mime_type = FORMATS
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, mime)| mime.to_string())
+        .ok_or_else(|| {
+            let supported: Vec<&str> = FORMATS.iter().map(|(ext, _)| *ext).collect();
+            CrabError::ConfigError(format!(
+                "Unsupported audio format '{}'. Supported: {}",
+                path.display(),
+                supported.join(", ")
+            ))
+        })?;
+
+    Ok(TranscriptionRequest {
+        model,
+        file_name: path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("audio")
+            .to_string(),
+        mime_type,
+        data: std::fs::read(path)?,
+    })
+}
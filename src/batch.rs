@@ -0,0 +1,151 @@
+//! Asynchronous batch jobs (`--batch-submit`, `--batch-status`, `--batch-fetch`).
+//!
+//! A job sends one chat request per input file, built from a prompt
+//! template. Submitted jobs are remembered in the config directory so their
+//! results can later be written back to one file per input, in input order.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::CrabError;
+use crate::types::BatchResult;
+
+/// A submitted batch job and what is needed to write its results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchJob {
+    pub id: String,
+    pub provider: String,
+    pub model: String,
+    /// Input files, in the order the requests were submitted.
+    pub inputs: Vec<PathBuf>,
+    /// Assistant prefill of the requests, restored at the start of each answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefill: Option<String>,
+    /// Submission time (Unix seconds).
+    pub submitted_at: u64,
+}
+
+/// All submitted batch jobs, oldest first.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BatchStore {
+    jobs: Vec<BatchJob>,
+}
+
+impl BatchStore {
+    fn store_path(config_dir: &Path) -> PathBuf {
+        config_dir.join("batches.json")
+    }
+
+    /// Load from disk. Returns an empty store on any read or parse failure.
+    pub fn load(config_dir: &Path) -> Self {
+        std::fs::read_to_string(Self::store_path(config_dir))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, config_dir: &Path) -> Result<(), CrabError> {
+        std::fs::create_dir_all(config_dir)?;
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(Self::store_path(config_dir), json)?;
+        Ok(())
+    }
+
+    pub fn add(&mut self, job: BatchJob) {
+        self.jobs.push(job);
+    }
+
+    pub fn get(&self, id: &str) -> Result<&BatchJob, CrabError> {
+        self.jobs
+            .iter()
+            .find(|j| j.id == id)
+            .ok_or_else(|| CrabError::ConfigError(format!("Unknown batch job: {id}")))
+    }
+
+    pub fn jobs(&self) -> &[BatchJob] {
+        &self.jobs
+    }
+}
+
+/// Returns the input files given as arguments, or else one path per
+/// non-empty line of `listing` (e.g. the output of `find` piped into STDIN).
+pub fn input_paths(args: &[PathBuf], listing: Option<&str>) -> Vec<PathBuf> {
+    if !args.is_empty() {
+        return args.to_vec();
+    }
+    listing
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Reads every input file. Fails on the first unreadable one, naming it.
+pub fn read_inputs(paths: &[PathBuf]) -> Result<Vec<String>, CrabError> {
+    paths
+        .iter()
+        .map(|path| {
+            std::fs::read_to_string(path).map_err(|e| {
+                CrabError::ConfigError(format!("Cannot read input {}: {e}", path.display()))
+            })
+        })
+        .collect()
+}
+
+/// Path of the result file for the input at `index`: the input's file
+/// stem prefixed with its zero-padded position, e.g. `0007-report.txt`.
+pub fn output_path(out_dir: &Path, index: usize, count: usize, input: &Path) -> PathBuf {
+    let width = count.to_string().len().max(4);
+    let stem = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "input".to_string());
+    out_dir.join(format!("{index:0width$}-{stem}.txt"))
+}
+
+/// Outcome of writing a job's results.
+pub struct Written {
+    /// Result files, in input order.
+    pub paths: Vec<PathBuf>,
+    /// Inputs without an answer, with the reason.
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+/// Writes each successful result to its input's result file in `out_dir`.
+/// Inputs missing from `results` are reported as failures.
+pub fn write_results(
+    job: &BatchJob,
+    results: Vec<BatchResult>,
+    out_dir: &Path,
+) -> Result<Written, CrabError> {
+    let mut outcomes: Vec<Option<Result<String, String>>> = vec![None; job.inputs.len()];
+    for result in results {
+        let Some(slot) = outcomes.get_mut(result.index) else {
+            continue;
+        };
+        *slot = Some(result.outcome.map(|completion| {
+            let prefill = job.prefill.as_deref().unwrap_or_default();
+            format!("{prefill}{}", completion.text)
+        }));
+    }
+
+    std::fs::create_dir_all(out_dir)?;
+    let mut written = Written {
+        paths: Vec::new(),
+        failures: Vec::new(),
+    };
+    for (index, (input, outcome)) in job.inputs.iter().zip(outcomes).enumerate() {
+        match outcome.unwrap_or_else(|| Err("No result returned".to_string())) {
+            Ok(text) => {
+                let path = output_path(out_dir, index, job.inputs.len(), input);
+                std::fs::write(&path, text)?;
+                written.paths.push(path);
+            }
+            Err(message) => written.failures.push((input.clone(), message)),
+        }
+    }
+    Ok(written)
+}
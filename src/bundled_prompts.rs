@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::Path;
 use include_dir::{include_dir, Dir};
 use crate::error::CrabError;
 
@@ -13,7 +13,7 @@ pub struct BundledPrompts;
 impl BundledPrompts {
     /// Install bundled prompts to the specified directory.
     /// Only creates files that don't already exist (never overwrites).
-    pub fn install_to(dir: &PathBuf) -> Result<usize, CrabError> {
+    pub fn install_to(dir: &Path) -> Result<usize, CrabError> {
         std::fs::create_dir_all(dir)?;
         
         let mut installed = 0;
@@ -37,7 +37,7 @@ impl BundledPrompts {
     }
 
     /// Check if any bundled prompts are missing from the directory.
-    pub fn has_missing_prompts(dir: &PathBuf) -> bool {
+    pub fn has_missing_prompts(dir: &Path) -> bool {
         if !dir.exists() {
             return true;
         }
@@ -0,0 +1,364 @@
+//! Record and replay of provider HTTP traffic (--record / --replay).
+//!
+//! Each exchange is saved as one JSON cassette file, named after the
+//! provider and a fingerprint of the request (method, URL and body) plus a
+//! counter for repeated identical requests. API keys and authentication
+//! headers are replaced by a placeholder before anything is written, so
+//! cassettes can be shared. Replay serves the response saved for the same
+//! request without touching the network.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use base64::Engine;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::CrabError;
+use crate::rate_limit;
+
+/// Replaces API keys and sensitive header values in cassettes. Also used as
+/// the API key of every provider while replaying.
+pub const REDACTED: &str = "REDACTED";
+
+/// Header and query parameter names containing any of these are redacted.
+const SENSITIVE_NAMES: &[&str] = &["auth", "key", "token", "secret", "cookie"];
+
+/// Quota headers such as `x-ratelimit-remaining-tokens` hold counts and
+/// reset times, not credentials; they are kept so replay sees the quota.
+const QUOTA_NAME: &str = "ratelimit";
+
+/// Response headers that describe the transfer rather than the content;
+/// not saved, since the replayed body is rebuilt from the cassette.
+const TRANSFER_HEADERS: &[&str] = &["content-length", "transfer-encoding", "content-encoding"];
+
+/// Whether provider traffic is recorded to or replayed from a directory.
+pub enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+/// Sets the mode for the rest of the process. Only the first call has an effect.
+pub fn set_mode(mode: Mode) {
+    let _ = MODE.set(mode);
+}
+
+pub fn mode() -> Option<&'static Mode> {
+    MODE.get()
+}
+
+pub fn replaying() -> bool {
+    matches!(mode(), Some(Mode::Replay(_)))
+}
+
+/// One saved request and its response.
+#[derive(Serialize, Deserialize)]
+struct Cassette {
+    provider: String,
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    /// JSON bodies as JSON, other text as a string. None for streamed
+    /// bodies such as multipart uploads.
+    body: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: BTreeMap<String, String>,
+    /// JSON bodies as JSON, other text as a string, binary data as base64.
+    body: Value,
+    #[serde(default)]
+    base64: bool,
+}
+
+/// A redacted request about to be sent, and the cassette file it maps to.
+pub struct Exchange {
+    provider: String,
+    file_name: String,
+    request: RecordedRequest,
+}
+
+impl Exchange {
+    /// Redacts `request` and picks its cassette file. `api_key` is removed
+    /// wherever it appears in the URL or body.
+    pub fn new(provider: &str, request: &Request, api_key: Option<&str>) -> Self {
+        let scrub = |text: &str| match api_key.filter(|k| !k.is_empty()) {
+            Some(key) => text.replace(key, REDACTED),
+            None => text.to_string(),
+        };
+        let method = request.method().to_string();
+        let url = scrub(&redact_url(request.url()));
+        let body = request
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|bytes| scrub(&String::from_utf8_lossy(bytes)));
+
+        let fingerprint = rate_limit::key_fingerprint(&format!(
+            "{method} {url}\n{}",
+            body.as_deref().unwrap_or_default()
+        ));
+        let file_name = format!(
+            "{provider}-{fingerprint}-{}.json",
+            occurrence(&format!("{provider}-{fingerprint}"))
+        );
+
+        Self {
+            provider: provider.to_string(),
+            file_name,
+            request: RecordedRequest {
+                method,
+                url,
+                headers: redact_headers(request.headers()),
+                body: body.map(|b| serde_json::from_str(&b).unwrap_or(Value::String(b))),
+            },
+        }
+    }
+}
+
+/// Saves the exchange in `dir` and returns an equivalent response, since
+/// reading the body consumes the original.
+pub async fn record(
+    dir: &Path,
+    exchange: Exchange,
+    response: Response,
+    api_key: Option<&str>,
+) -> Result<Response, CrabError> {
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = response.bytes().await?.to_vec();
+
+    let (body, base64) = match std::str::from_utf8(&bytes) {
+        Ok(text) => {
+            let text = match api_key.filter(|k| !k.is_empty()) {
+                Some(key) => text.replace(key, REDACTED),
+                None => text.to_string(),
+            };
+            (
+                serde_json::from_str(&text).unwrap_or(Value::String(text)),
+                false,
+            )
+        }
+        Err(_) => (
+            Value::String(base64::engine::general_purpose::STANDARD.encode(&bytes)),
+            true,
+        ),
+    };
+    let cassette = Cassette {
+        provider: exchange.provider,
+        request: exchange.request,
+        response: RecordedResponse {
+            status: status.as_u16(),
+            headers: redact_headers(&headers),
+            body,
+            base64,
+        },
+    };
+
+    std::fs::create_dir_all(dir)?;
+    let json = serde_json::to_string_pretty(&cassette)?;
+    std::fs::write(dir.join(&exchange.file_name), json)?;
+    Ok(build_response(status, &headers, bytes))
+}
+
+/// Returns the response saved in `dir` for the exchange's request.
+pub fn replay(dir: &Path, exchange: Exchange) -> Result<Response, CrabError> {
+    let path = dir.join(&exchange.file_name);
+    let contents = std::fs::read_to_string(&path).map_err(|_| CrabError::ProviderError {
+        provider: exchange.provider.clone(),
+        message: format!(
+            "No recorded response in {} for {} {}",
+            dir.display(),
+            exchange.request.method,
+            exchange.request.url
+        ),
+    })?;
+    let cassette: Cassette = serde_json::from_str(&contents)?;
+    let response = cassette.response;
+
+    let bytes = match response.body {
+        Value::String(text) if response.base64 => base64::engine::general_purpose::STANDARD
+            .decode(text)
+            .map_err(|e| {
+                CrabError::ConfigError(format!("Invalid base64 body in {}: {e}", path.display()))
+            })?,
+        Value::String(text) => text.into_bytes(),
+        other => other.to_string().into_bytes(),
+    };
+    let mut headers = HeaderMap::new();
+    for (name, value) in &response.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+    let status = StatusCode::from_u16(response.status)
+        .map_err(|_| CrabError::ConfigError(format!("Invalid status in {}", path.display())))?;
+    Ok(build_response(status, &headers, bytes))
+}
+
+fn build_response(status: StatusCode, headers: &HeaderMap, body: Vec<u8>) -> Response {
+    let mut response = http::Response::new(body);
+    *response.status_mut() = status;
+    for (name, value) in headers {
+        if !TRANSFER_HEADERS.contains(&name.as_str()) {
+            response.headers_mut().append(name, value.clone());
+        }
+    }
+    Response::from(response)
+}
+
+/// Returns how many times `key` was seen before in this process.
+fn occurrence(key: &str) -> usize {
+    static SEEN: OnceLock<Mutex<HashMap<String, usize>>> = OnceLock::new();
+    let Ok(mut seen) = SEEN.get_or_init(|| Mutex::new(HashMap::new())).lock() else {
+        return 0;
+    };
+    let count = seen.entry(key.to_string()).or_insert(0);
+    *count += 1;
+    *count - 1
+}
+
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_lowercase();
+    !name.contains(QUOTA_NAME) && SENSITIVE_NAMES.iter().any(|s| name.contains(s))
+}
+
+fn redact_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .filter(|(name, _)| !TRANSFER_HEADERS.contains(&name.as_str()))
+        .map(|(name, value)| {
+            let value = if is_sensitive(name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+fn redact_url(url: &reqwest::Url) -> String {
+    let mut url = url.clone();
+    if url.query().is_some() {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = if is_sensitive(&name) {
+                    REDACTED.to_string()
+                } else {
+                    value.into_owned()
+                };
+                (name.into_owned(), value)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str, body: &str, api_key: &str) -> Request {
+        reqwest::Client::new()
+            .post(url)
+            .bearer_auth(api_key)
+            .header("x-goog-api-key", api_key)
+            .body(body.to_string())
+            .build()
+            .unwrap()
+    }
+
+    /// The file name without its occurrence counter.
+    fn fingerprint(exchange: &Exchange) -> &str {
+        exchange.file_name.rsplit_once('-').unwrap().0
+    }
+
+    #[test]
+    fn redacts_key_from_url_headers_and_body() {
+        let key = "sk-test-0123456789abcdef";
+        let request = request(
+            &format!("https://api.example.com/v1/chat?key={key}&alt=sse"),
+            &format!(r#"{{"model":"m","api_key":"{key}"}}"#),
+            key,
+        );
+        let exchange = Exchange::new("redact", &request, Some(key));
+        let recorded = serde_json::to_string(&exchange.request).unwrap();
+
+        assert!(!recorded.contains(key), "{recorded}");
+        assert_eq!(
+            exchange.request.url,
+            "https://api.example.com/v1/chat?key=REDACTED&alt=sse"
+        );
+        assert_eq!(exchange.request.headers["authorization"], REDACTED);
+        assert_eq!(exchange.request.headers["x-goog-api-key"], REDACTED);
+        assert_eq!(
+            exchange.request.body,
+            Some(serde_json::json!({"model": "m", "api_key": REDACTED}))
+        );
+    }
+
+    #[test]
+    fn keeps_quota_headers() {
+        let mut headers = HeaderMap::new();
+        for name in [
+            "x-ratelimit-remaining-tokens",
+            "x-ratelimit-reset-tokens",
+            "anthropic-ratelimit-tokens-remaining",
+        ] {
+            headers.insert(name, HeaderValue::from_static("42"));
+        }
+        headers.insert("set-cookie", HeaderValue::from_static("session=abc"));
+        headers.insert("helicone-auth", HeaderValue::from_static("Bearer sk-helicone"));
+
+        let redacted = redact_headers(&headers);
+        assert_eq!(redacted["x-ratelimit-remaining-tokens"], "42");
+        assert_eq!(redacted["x-ratelimit-reset-tokens"], "42");
+        assert_eq!(redacted["anthropic-ratelimit-tokens-remaining"], "42");
+        assert_eq!(redacted["set-cookie"], REDACTED);
+        assert_eq!(redacted["helicone-auth"], REDACTED);
+    }
+
+    #[test]
+    fn key_does_not_change_fingerprint() {
+        let url = "https://api.example.com/v1/chat";
+        let body = r#"{"model":"m","messages":[]}"#;
+        let recorded = Exchange::new("fp", &request(url, body, "sk-live-1"), Some("sk-live-1"));
+        let replayed = Exchange::new("fp", &request(url, body, REDACTED), Some(REDACTED));
+        assert_eq!(fingerprint(&recorded), fingerprint(&replayed));
+    }
+
+    #[test]
+    fn fingerprint_depends_on_body() {
+        let url = "https://api.example.com/v1/chat";
+        let first = Exchange::new("body", &request(url, r#"{"n":1}"#, "k1"), None);
+        let second = Exchange::new("body", &request(url, r#"{"n":2}"#, "k1"), None);
+        assert_ne!(fingerprint(&first), fingerprint(&second));
+    }
+
+    #[test]
+    fn repeated_requests_get_numbered_files() {
+        let request = request("https://api.example.com/v1/models", "", "k1");
+        let first = Exchange::new("repeat", &request, None);
+        let second = Exchange::new("repeat", &request, None);
+        assert!(first.file_name.ends_with("-0.json"), "{}", first.file_name);
+        assert!(second.file_name.ends_with("-1.json"), "{}", second.file_name);
+    }
+}
@@ -23,7 +23,7 @@ fn styles() -> Styles {
     name = "crabai",
     version,
     about = "Minimal Unix-native multi-provider LLM CLI",
-    styles = styles()
+    styles = styles(),
 )]
 pub struct Cli {
     /// All remaining arguments after the options.
@@ -43,6 +43,197 @@ pub struct Cli {
     #[arg(short = 'T', long = "max-tokens")]
     pub max_tokens: Option<String>,
 
+    /// Start the model's answer with this text (assistant prefill). Overrides the prompt's `prefill`.
+    #[arg(long = "prefill", value_name = "TEXT")]
+    pub prefill: Option<String>,
+
+    /// Resume answers cut off by the token limit, up to N follow-up requests (default 3).
+    #[arg(
+        long = "continue-on-truncate",
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "3"
+    )]
+    pub continue_on_truncate: Option<u32>,
+
+    /// Number of independent answers to generate.
+    #[arg(
+        short = 'n',
+        long = "count",
+        value_name = "COUNT",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..=32)
+    )]
+    pub count: u32,
+
+    /// Text printed between answers when COUNT > 1 (`\n` and `\t` are expanded).
+    #[arg(long = "separator", value_name = "SEP", default_value = r"\n\n---\n\n")]
+    pub separator: String,
+
+    /// Embedding mode: print embedding vectors for STDIN instead of an answer.
+    #[arg(long = "embed")]
+    pub embed: bool,
+
+    /// Embed each non-empty line of STDIN separately (--embed).
+    #[arg(long = "lines", requires = "embed")]
+    pub lines: bool,
+
+    /// Output format for embeddings.
+    #[arg(
+        long = "embed-format",
+        value_name = "FORMAT",
+        value_enum,
+        default_value_t = crate::embed::EmbedFormat::Json
+    )]
+    pub embed_format: crate::embed::EmbedFormat,
+
+    /// Number of inputs sent per embeddings request.
+    #[arg(
+        long = "batch-size",
+        value_name = "N",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub batch_size: u32,
+
+    /// Size of the embedding vectors, for models that support shortening them.
+    #[arg(long = "dimensions", value_name = "N", requires = "embed")]
+    pub dimensions: Option<u32>,
+
+    /// Return token log-probabilities with up to K alternatives per token (0-20, default 0).
+    #[arg(
+        long = "logprobs",
+        value_name = "K",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0",
+        value_parser = clap::value_parser!(u8).range(0..=20)
+    )]
+    pub logprobs: Option<u8>,
+
+    /// Ground the answer in a web search (provider-native); sources are listed after it.
+    #[arg(long = "web")]
+    pub web: bool,
+
+    /// Print the answer and its metadata as a JSON document on STDOUT.
+    #[arg(long = "json")]
+    pub json: bool,
+
+    /// Attach an image (PNG, JPEG, GIF, WebP) or PDF for multimodal models. Repeatable.
+    #[arg(short = 'a', long = "attach", value_name = "FILE")]
+    pub attach: Vec<std::path::PathBuf>,
+
+    /// OpenRouter: upstream providers to try first, in order (comma-separated).
+    #[arg(long = "provider-order", value_name = "PROVIDERS", value_delimiter = ',')]
+    pub provider_order: Option<Vec<String>>,
+
+    /// OpenRouter: only use the upstream providers of --provider-order.
+    #[arg(long = "no-fallbacks")]
+    pub no_fallbacks: bool,
+
+    /// OpenRouter: whether upstream providers may store or train on prompts.
+    #[arg(long = "data-collection", value_name = "POLICY")]
+    pub data_collection: Option<crate::config::DataCollection>,
+
+    /// OpenRouter: accepted model quantizations (comma-separated, e.g. 'fp8,bf16').
+    #[arg(long = "quantizations", value_name = "LIST", value_delimiter = ',')]
+    pub quantizations: Option<Vec<crate::config::Quantization>>,
+
+    /// OpenRouter: fallback model, tried in order when the model fails. Repeatable.
+    #[arg(long = "fallback-model", value_name = "MODEL")]
+    pub fallback_models: Vec<String>,
+
+    /// OpenRouter: prompt transforms (comma-separated, e.g. 'middle-out').
+    #[arg(long = "transforms", value_name = "LIST", value_delimiter = ',')]
+    pub transforms: Option<Vec<String>>,
+
+    /// Transcribe this audio file and use the transcript as the prompt input.
+    #[arg(long = "audio", value_name = "FILE")]
+    pub audio: Option<std::path::PathBuf>,
+
+    /// Transcription model (e.g. 'groq:whisper-large-v3'). Defaults to the chat provider's.
+    #[arg(long = "audio-model", value_name = "PROVIDER:MODEL", requires = "audio")]
+    pub audio_model: Option<String>,
+
+    /// Print the transcript of --audio and exit.
+    #[arg(long = "transcript-only", requires = "audio")]
+    pub transcript_only: bool,
+
+    /// Image mode: generate images from the prompt and print the written paths.
+    #[arg(long = "image")]
+    pub image: bool,
+
+    /// Output file for --image (numbered when COUNT > 1) or --speak; directory for --batch-fetch.
+    #[arg(short = 'o', long = "output", value_name = "PATH")]
+    pub output: Option<std::path::PathBuf>,
+
+    /// Image size, e.g. 1024x1024 (or an aspect ratio such as 16:9 for Imagen).
+    #[arg(long = "size", value_name = "SIZE", requires = "image")]
+    pub size: Option<String>,
+
+    /// Image quality, e.g. standard/hd (DALL-E 3) or low/medium/high (gpt-image).
+    #[arg(long = "quality", value_name = "QUALITY", requires = "image")]
+    pub quality: Option<String>,
+
+    /// Speech mode: read STDIN (or the arguments) aloud and write the audio.
+    #[arg(long = "speak")]
+    pub speak: bool,
+
+    /// Voice for --speak (e.g. 'alloy' for OpenAI).
+    #[arg(long = "voice", value_name = "VOICE", requires = "speak")]
+    pub voice: Option<String>,
+
+    /// Audio format for --speak: mp3, opus, aac, flac, wav or pcm.
+    #[arg(long = "audio-format", value_name = "FORMAT", requires = "speak")]
+    pub audio_format: Option<String>,
+
+    /// Speaking speed for --speak (0.25 to 4.0).
+    #[arg(long = "speed", value_name = "SPEED", requires = "speak")]
+    pub speed: Option<f32>,
+
+    /// Fill-in-the-middle code completion: print only the code to insert at the cursor.
+    #[arg(long = "fim")]
+    pub fim: bool,
+
+    /// File with the code before the cursor (--fim).
+    #[arg(long = "prefix-file", value_name = "PATH", requires = "fim")]
+    pub prefix_file: Option<std::path::PathBuf>,
+
+    /// File with the code after the cursor (--fim).
+    #[arg(long = "suffix-file", value_name = "PATH", requires = "fim")]
+    pub suffix_file: Option<std::path::PathBuf>,
+
+    /// Marker that splits STDIN into prefix and suffix (--fim).
+    #[arg(long = "cursor", value_name = "MARKER", default_value = crate::fim::DEFAULT_CURSOR)]
+    pub cursor: String,
+
+    /// Submit a batch job with one request per input file and print its id.
+    /// Arguments: PROMPT [FILE]...
+    #[arg(long = "batch-submit", conflicts_with_all = ["batch_status", "batch_fetch"])]
+    pub batch_submit: bool,
+
+    /// Show the progress of a batch job, or of every submitted job.
+    #[arg(
+        long = "batch-status",
+        value_name = "ID",
+        num_args = 0..=1,
+        conflicts_with = "batch_fetch"
+    )]
+    pub batch_status: Option<Option<String>>,
+
+    /// Write the results of a finished batch job to one file per input in --output (default: .).
+    #[arg(long = "batch-fetch", value_name = "ID")]
+    pub batch_fetch: Option<String>,
+
+    /// Save every provider HTTP request and response as redacted cassette files in DIR.
+    #[arg(long = "record", value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<std::path::PathBuf>,
+
+    /// Serve provider responses from the cassette files in DIR instead of the network.
+    #[arg(long = "replay", value_name = "DIR")]
+    pub replay: Option<std::path::PathBuf>,
+
     /// Path to a custom config file.
     #[arg(short = 'u', long = "use-config")]
     pub use_config: Option<String>,
@@ -1,8 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
+use std::sync::OnceLock;
 
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
+use crate::api_key;
 use crate::error::CrabError;
 
 /// Configuration loaded from ~/.config/crabai/config.toml.
@@ -11,11 +14,21 @@ use crate::error::CrabError;
 pub struct Config {
     pub default_provider: Option<String>,
     pub default_model: Option<String>,
+    /// Embedding model used by --embed when `-m` is not given, in provider:model format.
+    pub default_embed_model: Option<String>,
+    /// Transcription model used by --audio, in provider:model format.
+    pub default_audio_model: Option<String>,
+    /// Text-to-speech model used by --speak, in provider:model format.
+    pub default_speech_model: Option<String>,
     pub temperature: Option<f32>,
     pub max_tokens: Option<u32>,
     pub prompts_dir: Option<String>,
     pub model_cache: Option<bool>,
     pub model_cache_ttl_hours: Option<u64>,
+    pub rate_limit_wait: Option<bool>,
+    pub rate_limit_max_wait_secs: Option<u64>,
+    /// How long --list-models waits for each provider's model list.
+    pub list_models_timeout_secs: Option<u64>,
 
     /// Advanced configuration for provider-specific settings.
     pub advanced: Option<AdvancedConfig>,
@@ -34,8 +47,71 @@ pub struct AdvancedConfig {
     /// for that provider is used (e.g., "OPENAI_API_KEY" for OpenAI).
     pub api_key_vars: Option<HashMap<String, String>>,
 
+    /// Commands that print a provider's API key, run with the shell once per
+    /// process. Take precedence over `api_key_files` and `api_key_vars`.
+    ///
+    /// Example: { "openai": "pass show openai" }
+    pub api_key_commands: Option<HashMap<String, String>>,
+
+    /// Files containing a provider's API key. Files readable by group or
+    /// others are refused. Take precedence over `api_key_vars`.
+    ///
+    /// Example: { "anthropic": "~/.secrets/anthropic" }
+    pub api_key_files: Option<HashMap<String, String>>,
+
+    /// Several API keys per provider, used in order. When a key hits a rate
+    /// limit or quota error, requests switch to the next one. Replaces
+    /// `api_key_commands`, `api_key_files` and `api_key_vars` for the
+    /// providers listed.
+    ///
+    /// Example: { "openai": [{ var = "OPENAI_KEY_A" }, { command = "pass show openai-b" }] }
+    pub api_keys: Option<HashMap<String, Vec<ApiKeySource>>>,
+
+    /// Extra HTTP headers sent with every request to a provider.
+    /// Maps provider name (lowercase) to a table of header names and values.
+    /// Values may reference environment variables as `${VAR}`.
+    ///
+    /// Example: { "openai": { "OpenAI-Organization": "${OPENAI_ORG_ID}" } }
+    pub headers: Option<HashMap<String, HashMap<String, String>>>,
+
+    /// Extra query parameters appended to every request URL for a provider.
+    /// Same layout and `${VAR}` interpolation as `headers`.
+    pub query: Option<HashMap<String, HashMap<String, String>>>,
+
+    /// API base URL overrides, for proxies, compatible gateways or a local
+    /// stand-in server. Maps provider name (lowercase) to the URL that
+    /// replaces the provider's default base (e.g. "https://api.openai.com/v1").
+    ///
+    /// Example: { "openai": "http://localhost:8080/v1" }
+    pub base_urls: Option<HashMap<String, String>>,
+
     /// Provider-specific advanced settings for OpenAI.
     pub openai: Option<OpenAIAdvancedConfig>,
+
+    /// Provider-specific advanced settings for Google Gemini.
+    pub google: Option<GoogleAdvancedConfig>,
+
+    /// Routing preferences and fallbacks for OpenRouter.
+    pub openrouter: Option<OpenRouterAdvancedConfig>,
+
+    /// Settings for the built-in offline `mock` provider.
+    pub mock: Option<MockAdvancedConfig>,
+
+    /// Provider plugins declared explicitly, in addition to the
+    /// `crabai-provider-<name>` executables found on PATH. Maps provider
+    /// name to the plugin executable.
+    ///
+    /// Example: { "acme": "~/bin/acme-llm-bridge" }
+    pub plugins: Option<HashMap<String, String>>,
+}
+
+/// One entry of `advanced.api_keys`: an environment variable, a command or a
+/// file. Exactly one must be set.
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct ApiKeySource {
+    pub var: Option<String>,
+    pub command: Option<String>,
+    pub file: Option<String>,
 }
 
 /// Advanced settings specific to the OpenAI provider.
@@ -47,6 +123,115 @@ pub struct OpenAIAdvancedConfig {
     pub max_tokens_param: Option<String>,
 }
 
+/// Advanced settings specific to the Google Gemini provider.
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct GoogleAdvancedConfig {
+    /// Blocking threshold per harm category, sent as `safetySettings`.
+    /// Categories may be given without the `HARM_CATEGORY_` prefix and in
+    /// any case; thresholds are Gemini's (`BLOCK_NONE`, `BLOCK_ONLY_HIGH`,
+    /// `BLOCK_MEDIUM_AND_ABOVE`, `BLOCK_LOW_AND_ABOVE`, `OFF`).
+    ///
+    /// Example: { "harassment": "BLOCK_ONLY_HIGH", "dangerous_content": "BLOCK_NONE" }
+    pub safety_settings: Option<BTreeMap<String, String>>,
+}
+
+/// Routing preferences and fallbacks sent with every OpenRouter request.
+/// Unset fields are left to OpenRouter's defaults.
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct OpenRouterAdvancedConfig {
+    /// Upstream providers to try first, in order (e.g. ["anthropic", "together"]).
+    pub order: Option<Vec<String>>,
+    /// Whether other upstream providers may serve the request when those in
+    /// `order` are unavailable.
+    pub allow_fallbacks: Option<bool>,
+    /// "deny" restricts routing to upstream providers that do not store or
+    /// train on prompts; "allow" permits all. "deny" here cannot be
+    /// overridden from the command line.
+    pub data_collection: Option<DataCollection>,
+    /// Accepted model quantizations (e.g. ["fp8", "bf16"]).
+    pub quantizations: Option<Vec<Quantization>>,
+    /// Fallback models, tried in order when the requested model fails.
+    pub models: Option<Vec<String>>,
+    /// Prompt transforms (e.g. ["middle-out"]).
+    pub transforms: Option<Vec<String>>,
+}
+
+/// OpenRouter `data_collection` policy.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DataCollection {
+    Allow,
+    Deny,
+}
+
+/// Model quantizations OpenRouter can route by.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    Int4,
+    Int8,
+    Fp4,
+    Fp6,
+    Fp8,
+    Fp16,
+    Bf16,
+    Fp32,
+    Unknown,
+}
+
+impl OpenRouterAdvancedConfig {
+    /// Applies the settings given on the command line. A configured
+    /// `data_collection = "deny"` stays in force; returns the flag that was
+    /// ignored because of it, if any.
+    pub fn merge(&mut self, overrides: OpenRouterAdvancedConfig) -> Option<&'static str> {
+        let mut ignored = None;
+        if self.data_collection != Some(DataCollection::Deny) {
+            self.data_collection = overrides.data_collection.or(self.data_collection.take());
+        } else if overrides.data_collection == Some(DataCollection::Allow) {
+            ignored = Some("--data-collection allow");
+        }
+        self.order = overrides.order.or(self.order.take());
+        self.allow_fallbacks = overrides.allow_fallbacks.or(self.allow_fallbacks);
+        self.quantizations = overrides.quantizations.or(self.quantizations.take());
+        self.models = overrides.models.or(self.models.take());
+        self.transforms = overrides.transforms.or(self.transforms.take());
+        ignored
+    }
+
+    /// Rejects lists that would be sent empty, or with empty entries.
+    pub fn validate(&self) -> Result<(), CrabError> {
+        let lists = [
+            ("order", &self.order),
+            ("models", &self.models),
+            ("transforms", &self.transforms),
+        ];
+        for (name, list) in lists {
+            let Some(list) = list else {
+                continue;
+            };
+            if list.is_empty() || list.iter().any(|entry| entry.trim().is_empty()) {
+                return Err(CrabError::ConfigError(format!(
+                    "OpenRouter {name} must list at least one entry and no empty ones"
+                )));
+            }
+        }
+        if self.quantizations.as_ref().is_some_and(Vec::is_empty) {
+            return Err(CrabError::ConfigError(
+                "OpenRouter quantizations must list at least one entry".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Settings for the built-in offline `mock` provider.
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct MockAdvancedConfig {
+    /// Path of the TOML file with the canned responses of `mock:fixtures`.
+    /// Defaults to ~/.config/crabai/mock_fixtures.toml.
+    pub fixtures: Option<String>,
+}
+
 impl Config {
     /// Load configuration from a TOML file.
     ///
@@ -65,6 +250,9 @@ impl Config {
 
         let contents = std::fs::read_to_string(&config_path)?;
         let config: Config = toml::from_str(&contents)?;
+        if let Some(routing) = config.advanced.as_ref().and_then(|a| a.openrouter.as_ref()) {
+            routing.validate()?;
+        }
         Ok(config)
     }
 
@@ -107,6 +295,20 @@ impl Config {
         self.model_cache_ttl_hours.unwrap_or(24)
     }
 
+    /// Whether to wait for a known-exhausted rate limit quota to reset
+    /// before sending a request.
+    pub fn rate_limit_wait_enabled(&self) -> bool {
+        self.rate_limit_wait.unwrap_or(true)
+    }
+
+    pub fn rate_limit_max_wait_secs(&self) -> u64 {
+        self.rate_limit_max_wait_secs.unwrap_or(60)
+    }
+
+    pub fn list_models_timeout_secs(&self) -> u64 {
+        self.list_models_timeout_secs.unwrap_or(10)
+    }
+
     pub fn resolve_temperature(&self) -> f32 {
         self.temperature.unwrap_or(0.2)
     }
@@ -130,6 +332,62 @@ impl Config {
         Self::default_api_key_var(provider)
     }
 
+    /// Returns the provider's API key to use next, looking it up if needed
+    /// (see `api_key_sources`).
+    pub fn api_key(&self, provider: &str) -> Result<Option<String>, CrabError> {
+        Ok(self.api_key_sources(provider)?.active()?.map(str::to_string))
+    }
+
+    /// Returns where to look for the provider's API keys: the sources listed
+    /// in `api_keys`, or its command, key file or environment variable.
+    /// Nothing is looked up yet (see `api_key::Keys`). While replaying
+    /// recorded traffic, a placeholder key is used instead, so no real key
+    /// is needed.
+    pub fn api_key_sources(&self, provider: &str) -> Result<api_key::Keys, CrabError> {
+        if crate::cassette::replaying() {
+            let placeholder = vec![crate::cassette::REDACTED.to_string()];
+            return Ok(api_key::Keys::fixed(provider, placeholder));
+        }
+        let advanced = self.advanced.as_ref();
+        let listed = advanced
+            .and_then(|a| a.api_keys.as_ref())
+            .and_then(|keys| keys.get(provider));
+        let lookups = match listed {
+            Some(sources) => sources
+                .iter()
+                .map(|source| {
+                    let set = [&source.var, &source.command, &source.file]
+                        .iter()
+                        .filter(|s| s.is_some())
+                        .count();
+                    if set != 1 {
+                        return Err(CrabError::ConfigError(format!(
+                            "Each advanced.api_keys.{provider} entry needs exactly one of \
+                             var, command or file"
+                        )));
+                    }
+                    Ok(api_key::Lookup {
+                        command: source.command.clone(),
+                        file: source.file.as_deref().map(|f| PathBuf::from(shellexpand(f))),
+                        var: source.var.clone(),
+                    })
+                })
+                .collect::<Result<Vec<_>, CrabError>>()?,
+            None => vec![api_key::Lookup {
+                command: advanced
+                    .and_then(|a| a.api_key_commands.as_ref())
+                    .and_then(|commands| commands.get(provider))
+                    .cloned(),
+                file: advanced
+                    .and_then(|a| a.api_key_files.as_ref())
+                    .and_then(|files| files.get(provider))
+                    .map(|path| PathBuf::from(shellexpand(path))),
+                var: Some(self.api_key_var(provider)),
+            }],
+        };
+        Ok(api_key::Keys::new(provider, lookups))
+    }
+
     /// Returns the standard default environment variable name for a provider.
     /// This is the built-in convention used when no custom mapping is configured.
     pub fn default_api_key_var(provider: &str) -> String {
@@ -147,6 +405,68 @@ impl Config {
         .to_string()
     }
 
+    /// Returns the extra HTTP headers configured for a provider, with
+    /// `${VAR}` references resolved from the environment.
+    pub fn provider_headers(&self, provider: &str) -> Result<Vec<(String, String)>, CrabError> {
+        let table = self
+            .advanced
+            .as_ref()
+            .and_then(|a| a.headers.as_ref())
+            .and_then(|h| h.get(provider));
+        resolve_table(table)
+    }
+
+    /// Returns the extra query parameters configured for a provider, with
+    /// `${VAR}` references resolved from the environment.
+    pub fn provider_query(&self, provider: &str) -> Result<Vec<(String, String)>, CrabError> {
+        let table = self
+            .advanced
+            .as_ref()
+            .and_then(|a| a.query.as_ref())
+            .and_then(|q| q.get(provider));
+        resolve_table(table)
+    }
+
+    /// Returns the API base URL for a provider: the configured override, or
+    /// `default`. A trailing slash is removed.
+    pub fn provider_base_url(&self, provider: &str, default: &str) -> String {
+        self.advanced
+            .as_ref()
+            .and_then(|a| a.base_urls.as_ref())
+            .and_then(|b| b.get(provider))
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    /// Returns the fixtures file of the `mock` provider.
+    /// Expands tilde (~/) in the configured path if present.
+    pub fn mock_fixtures_path(&self) -> PathBuf {
+        match self
+            .advanced
+            .as_ref()
+            .and_then(|a| a.mock.as_ref())
+            .and_then(|m| m.fixtures.as_deref())
+        {
+            Some(path) => PathBuf::from(shellexpand(path)),
+            None => Self::config_dir().join("mock_fixtures.toml"),
+        }
+    }
+
+    /// Returns the declared provider plugins by name, with tilde (~/)
+    /// expanded in their paths.
+    pub fn plugin_paths(&self) -> BTreeMap<String, PathBuf> {
+        self.advanced
+            .as_ref()
+            .and_then(|a| a.plugins.as_ref())
+            .map(|plugins| {
+                plugins
+                    .iter()
+                    .map(|(name, path)| (name.clone(), PathBuf::from(shellexpand(path))))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     /// Serializes and writes the config to a TOML file.
     /// Creates parent directories if they don't exist.
     pub fn save(&self, path: &PathBuf) -> Result<(), CrabError> {
@@ -159,6 +479,47 @@ impl Config {
     }
 }
 
+/// Interpolates every value of a name/value table and returns the pairs
+/// sorted by name so requests are built deterministically.
+fn resolve_table(
+    table: Option<&HashMap<String, String>>,
+) -> Result<Vec<(String, String)>, CrabError> {
+    let mut pairs = Vec::new();
+    if let Some(table) = table {
+        for (name, value) in table {
+            pairs.push((name.clone(), interpolate_env(value)?));
+        }
+    }
+    pairs.sort();
+    Ok(pairs)
+}
+
+/// Replaces `${VAR}` references with the value of the environment variable.
+/// A reference to an unset variable is a configuration error, so a missing
+/// secret never silently turns into an empty header.
+pub fn interpolate_env(value: &str) -> Result<String, CrabError> {
+    static RE: OnceLock<regex::Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        regex::Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").expect("valid regex")
+    });
+    let mut result = String::with_capacity(value.len());
+    let mut last = 0;
+    for caps in re.captures_iter(value) {
+        let whole = caps.get(0).expect("match");
+        let var = &caps[1];
+        let resolved = std::env::var(var).map_err(|_| {
+            CrabError::ConfigError(format!(
+                "Environment variable {var} referenced in config is not set"
+            ))
+        })?;
+        result.push_str(&value[last..whole.start()]);
+        result.push_str(&resolved);
+        last = whole.end();
+    }
+    result.push_str(&value[last..]);
+    Ok(result)
+}
+
 /// Expands tilde (~/) at the start of a path to the user's home directory.
 /// Does not support ~username syntax or $VAR interpolation.
 /// Returns the path unchanged if tilde expansion is not applicable.
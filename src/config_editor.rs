@@ -5,6 +5,7 @@ use std::path::PathBuf;
 use crate::config::Config;
 use crate::error::CrabError;
 use crate::providers::{self, get_provider_with_config};
+use crate::types::ProviderName;
 
 /// Interactive configuration wizard for creating or editing CrabAI config files.
 ///
@@ -46,14 +47,14 @@ pub async fn run_interactive_config(config_path: Option<&str>) -> Result<(), Cra
 
     // Provider and model selection loop
     let (_provider, selected_model) = loop {
-        let provider_names = providers::list_provider_names();
+        let provider_names = providers::list_provider_names(&config);
         let default_provider_name = config
             .default_provider
             .as_deref()
-            .unwrap_or(provider_names[0]);
+            .unwrap_or(&provider_names[0]);
         let default_idx = provider_names
             .iter()
-            .position(|&n| n == default_provider_name)
+            .position(|n| n == default_provider_name)
             .unwrap_or(0);
 
         let provider_idx = Select::with_theme(&theme)
@@ -62,7 +63,7 @@ pub async fn run_interactive_config(config_path: Option<&str>) -> Result<(), Cra
             .default(default_idx)
             .interact()?;
 
-        let selected_provider = provider_names[provider_idx];
+        let selected_provider = provider_names[provider_idx].as_str();
         config.default_provider = Some(selected_provider.to_string());
 
         println!(
@@ -135,7 +136,7 @@ pub async fn run_interactive_config(config_path: Option<&str>) -> Result<(), Cra
         // otherwise, use the model's maximum as the default to avoid confusion.
         let default_prompt_val = config
             .max_tokens
-            .filter(|&cfg_max| model_max_tokens.map_or(true, |model_max| cfg_max <= model_max))
+            .filter(|&cfg_max| model_max_tokens.is_none_or(|model_max| cfg_max <= model_max))
             .unwrap_or(default_val);
 
         let max_tokens: String = Input::with_theme(&theme)
@@ -198,8 +199,9 @@ pub async fn run_interactive_config(config_path: Option<&str>) -> Result<(), Cra
         println!("Press Enter to keep the default.\n");
     }
 
-    // Ensure all providers have their default API key vars in the map
-    for provider_name in providers::list_provider_names() {
+    // Ensure all built-in providers have their default API key vars in the
+    // map; plugins handle their own authentication.
+    for provider_name in ProviderName::ALL.iter().map(|p| p.as_str()) {
         let default_var = Config::default_api_key_var(provider_name);
 
         if configure_advanced {
@@ -0,0 +1,80 @@
+//! Automatic continuation of answers truncated by the output token limit.
+//!
+//! When a provider reports a length stop reason, the partial answer is sent
+//! back as an assistant turn followed by a request to continue. The pieces
+//! are stitched together, dropping any text the model repeats at the seam.
+
+use crate::error::CrabError;
+use crate::providers::Provider;
+use crate::types::{ChatMessage, ChatRequest, Completion};
+
+/// Follow-up user turn asking the model to resume a truncated answer.
+const CONTINUE_PROMPT: &str = "Continue exactly where your previous answer stopped. \
+Do not repeat any text you already wrote and do not add any preamble.";
+
+/// Shortest repeated text treated as overlap at a seam. Shorter matches are
+/// too likely to be coincidental (a shared letter or space).
+const MIN_OVERLAP: usize = 8;
+
+/// Longest overlap searched for at a seam, in bytes.
+const MAX_OVERLAP: usize = 2000;
+
+/// Result of a continued completion.
+pub struct Continued {
+    pub completion: Completion,
+    /// Number of follow-up requests that were sent.
+    pub rounds: u32,
+}
+
+/// Keeps asking the model to continue while `first` is truncated, up to
+/// `max_rounds` follow-up requests. Output token counts are summed, token
+/// log-probabilities concatenated and web sources merged.
+pub async fn continue_truncated(
+    provider: &dyn Provider,
+    request: &ChatRequest,
+    first: Completion,
+    max_rounds: u32,
+) -> Result<Continued, CrabError> {
+    let mut completion = first;
+    let mut rounds = 0;
+
+    while completion.is_truncated() && rounds < max_rounds {
+        // The partial answer already starts with any prefill.
+        let mut follow_up = request.clone();
+        follow_up.prefill = None;
+        follow_up.messages.push(ChatMessage::assistant(&completion.text));
+        follow_up.messages.push(ChatMessage::user(CONTINUE_PROMPT));
+
+        let next = provider.send(&follow_up).await?;
+        rounds += 1;
+
+        completion.text = stitch(&completion.text, &next.text);
+        completion.finish_reason = next.finish_reason;
+        completion.output_tokens = match (completion.output_tokens, next.output_tokens) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        if let (Some(logprobs), Some(more)) = (&mut completion.logprobs, next.logprobs) {
+            logprobs.extend(more);
+        }
+        completion.add_sources(next.sources);
+    }
+
+    Ok(Continued { completion, rounds })
+}
+
+/// Appends `next` to `text`, removing the longest prefix of `next` that
+/// repeats the end of `text`.
+pub fn stitch(text: &str, next: &str) -> String {
+    let limit = MAX_OVERLAP.min(text.len()).min(next.len());
+    let overlap = (MIN_OVERLAP..=limit)
+        .rev()
+        .filter(|&n| next.is_char_boundary(n))
+        .find(|&n| text.ends_with(&next[..n]))
+        .unwrap_or(0);
+
+    let mut stitched = String::with_capacity(text.len() + next.len() - overlap);
+    stitched.push_str(text);
+    stitched.push_str(&next[overlap..]);
+    stitched
+}
@@ -0,0 +1,101 @@
+//! Embedding mode (--embed): splits STDIN into inputs, sends them to the
+//! provider's embeddings endpoint in batches, and formats the vectors.
+
+use clap::ValueEnum;
+use serde_json::json;
+
+use crate::error::CrabError;
+use crate::providers::Provider;
+use crate::types::EmbedRequest;
+
+/// Output format for --embed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EmbedFormat {
+    /// One JSON document with every input and its vector.
+    Json,
+    /// One JSON object per line: index, text and vector.
+    Jsonl,
+    /// One bare JSON array of floats per line.
+    Raw,
+}
+
+/// Returns the texts to embed: the whole input, or each non-empty line.
+pub fn split_inputs(input: &str, lines: bool) -> Vec<String> {
+    if lines {
+        input
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(str::to_string)
+            .collect()
+    } else if input.trim().is_empty() {
+        Vec::new()
+    } else {
+        vec![input.to_string()]
+    }
+}
+
+/// Embeds `inputs` with at most `batch_size` texts per request, preserving
+/// input order.
+pub async fn embed_all(
+    provider: &dyn Provider,
+    model: &str,
+    inputs: &[String],
+    batch_size: usize,
+    dimensions: Option<u32>,
+) -> Result<Vec<Vec<f32>>, CrabError> {
+    let mut vectors = Vec::with_capacity(inputs.len());
+    for batch in inputs.chunks(batch_size.max(1)) {
+        let request = EmbedRequest {
+            model: model.to_string(),
+            inputs: batch.to_vec(),
+            dimensions,
+        };
+        vectors.extend(provider.embed(&request).await?);
+    }
+    Ok(vectors)
+}
+
+/// Formats the vectors for STDOUT. Every format ends with a newline.
+pub fn format(
+    format: EmbedFormat,
+    provider: &str,
+    model: &str,
+    inputs: &[String],
+    vectors: &[Vec<f32>],
+) -> Result<String, CrabError> {
+    let mut out = String::new();
+    match format {
+        EmbedFormat::Json => {
+            let data: Vec<_> = inputs
+                .iter()
+                .zip(vectors)
+                .enumerate()
+                .map(|(index, (text, vector))| {
+                    json!({ "index": index, "text": text, "embedding": vector })
+                })
+                .collect();
+            let doc = json!({
+                "provider": provider,
+                "model": model,
+                "dimensions": vectors.first().map(Vec::len),
+                "data": data,
+            });
+            out.push_str(&serde_json::to_string(&doc)?);
+            out.push('\n');
+        }
+        EmbedFormat::Jsonl => {
+            for (index, (text, vector)) in inputs.iter().zip(vectors).enumerate() {
+                let line = json!({ "index": index, "text": text, "embedding": vector });
+                out.push_str(&serde_json::to_string(&line)?);
+                out.push('\n');
+            }
+        }
+        EmbedFormat::Raw => {
+            for vector in vectors {
+                out.push_str(&serde_json::to_string(vector)?);
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use thiserror::Error;
 
 /// Error types for CrabAI operations.
@@ -12,10 +14,46 @@ pub enum CrabError {
     #[error("Provider {provider} error: {message}")]
     ProviderError { provider: String, message: String },
 
+    /// Provider rejected the API key or the key lacks access (HTTP 401/403).
+    #[error("Authentication failed: {0}\n  Hint: check the API key for this provider and that it has access to the model.")]
+    Auth(ApiError),
+
+    /// Provider throttled the request or the account quota is exhausted (HTTP 429).
+    #[error("Rate limited: {0}\n  Hint: wait and retry, or check the account's quota and billing.")]
+    RateLimited(ApiError),
+
+    /// Prompt plus requested output exceeds the model's context window.
+    #[error("Context length exceeded: {0}\n  Hint: shorten the input or lower --max-tokens.")]
+    ContextLength(ApiError),
+
+    /// Provider rejected a request parameter (HTTP 400/422).
+    #[error("Invalid parameter: {0}\n  Hint: check --temperature and --max-tokens against the model's limits; use -v to see what was sent.")]
+    InvalidParameter(ApiError),
+
+    /// Provider's safety or moderation system blocked the prompt or the answer.
+    #[error("Content filtered: {0}\n  Hint: the provider's safety system blocked this request; rephrase the input.")]
+    ContentFiltered(ApiError),
+
+    /// Provider failed or is overloaded (HTTP 5xx).
+    #[error("Server error: {0}\n  Hint: the provider is having problems; retry later.")]
+    ServerError(ApiError),
+
+    /// Requested model does not exist or is not available to this key.
+    #[error("Model not found: {0}\n  Hint: check the model id with 'crabai --list-models'.")]
+    ModelNotFound(ApiError),
+
     /// Configuration file parsing error or invalid configuration.
     #[error("Configuration error: {0}")]
     ConfigError(String),
 
+    /// Requested feature is not available for the selected provider or model.
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
+    /// Batch job results were requested before the job finished.
+    #[error("Batch job not finished: {0}\n  Hint: check its progress with 'crabai --batch-status ID'.")]
+    BatchPending(String),
+
     /// Requested prompt template file not found in prompts directory.
     #[error("Prompt not found: {0}")]
     PromptNotFound(String),
@@ -49,6 +87,65 @@ pub enum CrabError {
     Dialoguer(#[from] dialoguer::Error),
 }
 
+/// Details of a failed provider API call, parsed from the HTTP response.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub provider: String,
+    pub status: u16,
+    /// Error message from the provider's JSON error body (or the raw body).
+    pub message: String,
+    /// Provider-assigned request id, useful when contacting support.
+    pub request_id: Option<String>,
+    /// Seconds to wait before retrying, from the Retry-After header.
+    pub retry_after: Option<u64>,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} HTTP {}: {}", self.provider, self.status, self.message)?;
+        if let Some(id) = &self.request_id {
+            write!(f, " (request id: {id})")?;
+        }
+        if let Some(secs) = self.retry_after {
+            write!(f, " (retry after {secs}s)")?;
+        }
+        Ok(())
+    }
+}
+
+impl CrabError {
+    /// Returns the provider's message for errors that can name a model
+    /// constraint: a rejected parameter or an unclassified provider error.
+    /// Used by the self-healing logic to learn model constraints.
+    ///
+    /// Other errors say nothing about the model's parameters, and their
+    /// messages would be misread: "prompt is too long: 208310 tokens >
+    /// 200000 maximum" is about the input, not the output token limit.
+    pub fn constraint_message(&self) -> Option<&str> {
+        match self {
+            CrabError::ProviderError { message, .. } => Some(message),
+            CrabError::InvalidParameter(e) => Some(&e.message),
+            _ => None,
+        }
+    }
+
+    /// Process exit code for this error, so scripts can tell failure
+    /// categories apart without parsing STDERR.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CrabError::Auth(_) | CrabError::MissingApiKey(_) => 3,
+            CrabError::RateLimited(_) => 4,
+            CrabError::ContextLength(_) => 5,
+            CrabError::InvalidParameter(_) => 6,
+            CrabError::ContentFiltered(_) => 7,
+            CrabError::ServerError(_) => 8,
+            CrabError::ModelNotFound(_) => 9,
+            CrabError::BatchPending(_) => 10,
+            _ => 1,
+        }
+    }
+}
+
 impl From<arboard::Error> for CrabError {
     fn from(e: arboard::Error) -> Self {
         CrabError::ClipboardError(e.to_string())
@@ -60,3 +157,40 @@ impl From<Box<dyn std::error::Error>> for CrabError {
         CrabError::ClipboardError(e.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(message: &str) -> ApiError {
+        ApiError {
+            provider: "anthropic".to_string(),
+            status: 400,
+            message: message.to_string(),
+            request_id: None,
+            retry_after: None,
+        }
+    }
+
+    #[test]
+    fn constraint_message_ignores_errors_about_the_request() {
+        let message = "prompt is too long: 208310 tokens > 200000 maximum";
+        assert_eq!(CrabError::ContextLength(api_error(message)).constraint_message(), None);
+        assert_eq!(CrabError::Auth(api_error("invalid x-api-key")).constraint_message(), None);
+        assert_eq!(CrabError::RateLimited(api_error("slow down")).constraint_message(), None);
+        assert_eq!(CrabError::ContentFiltered(api_error("blocked")).constraint_message(), None);
+    }
+
+    #[test]
+    fn constraint_message_returns_parameter_errors() {
+        let message = "max_tokens: 100000 > 64000, which is the maximum allowed";
+        let error = CrabError::InvalidParameter(api_error(message));
+        assert_eq!(error.constraint_message(), Some(message));
+
+        let error = CrabError::ProviderError {
+            provider: "mistral".to_string(),
+            message: "temperature is not supported".to_string(),
+        };
+        assert_eq!(error.constraint_message(), Some("temperature is not supported"));
+    }
+}
@@ -0,0 +1,48 @@
+//! Fill-in-the-middle input handling for --fim: the code before and after
+//! the insertion point comes from --prefix-file/--suffix-file, or from
+//! STDIN split at a cursor marker.
+
+use std::path::Path;
+
+use crate::error::CrabError;
+
+/// Marker that separates prefix and suffix in STDIN, unless --cursor is given.
+pub const DEFAULT_CURSOR: &str = "<CURSOR>";
+
+/// Splits `input` at the first occurrence of `marker`. Without a marker the
+/// whole input is the prefix and the suffix is empty.
+pub fn split_at_cursor(input: &str, marker: &str) -> (String, String) {
+    match input.split_once(marker) {
+        Some((prefix, suffix)) => (prefix.to_string(), suffix.to_string()),
+        None => (input.to_string(), String::new()),
+    }
+}
+
+/// Resolves prefix and suffix: from the given files (a missing side is
+/// empty), or else from STDIN split at the cursor marker.
+pub fn read_input(
+    prefix_file: Option<&Path>,
+    suffix_file: Option<&Path>,
+    stdin: Option<&str>,
+    marker: &str,
+) -> Result<(String, String), CrabError> {
+    let read = |path: Option<&Path>| -> Result<String, CrabError> {
+        Ok(match path {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => String::new(),
+        })
+    };
+    let (prefix, suffix) = if prefix_file.is_some() || suffix_file.is_some() {
+        (read(prefix_file)?, read(suffix_file)?)
+    } else {
+        split_at_cursor(stdin.unwrap_or_default(), marker)
+    };
+
+    if prefix.trim().is_empty() && suffix.trim().is_empty() {
+        return Err(CrabError::ConfigError(
+            "No code to complete. Pipe code with a cursor marker into stdin or use --prefix-file/--suffix-file."
+                .to_string(),
+        ));
+    }
+    Ok((prefix, suffix))
+}
@@ -0,0 +1,113 @@
+//! Image mode (--image): validates size, quality and count against what the
+//! model accepts, then writes the generated images to disk.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+
+use crate::error::CrabError;
+use crate::types::{GeneratedImage, ImageOptions, ImageRequest};
+
+/// Checks the request against the model's options. Models without known
+/// options are passed through for the provider API to judge.
+pub fn validate(request: &ImageRequest, options: Option<&ImageOptions>) -> Result<(), CrabError> {
+    let Some(options) = options else {
+        return Ok(());
+    };
+    let model = &request.model;
+
+    if request.count > options.max_count {
+        return Err(CrabError::ConfigError(format!(
+            "{model} generates at most {} image(s) per request",
+            options.max_count
+        )));
+    }
+
+    if let Some(size) = &request.size {
+        if options.any_size {
+            static RE: OnceLock<Regex> = OnceLock::new();
+            let re = RE.get_or_init(|| Regex::new(r"^\d+x\d+$").expect("valid regex"));
+            if !re.is_match(size) {
+                return Err(CrabError::ConfigError(format!(
+                    "Invalid size '{size}' for {model}; expected WIDTHxHEIGHT"
+                )));
+            }
+        } else if options.sizes.is_empty() {
+            return Err(CrabError::ConfigError(format!("{model} has no size option")));
+        } else if !options.sizes.contains(&size.as_str()) {
+            return Err(CrabError::ConfigError(format!(
+                "Invalid size '{size}' for {model}; supported: {}",
+                options.sizes.join(", ")
+            )));
+        }
+    }
+
+    if let Some(quality) = &request.quality {
+        if options.qualities.is_empty() {
+            return Err(CrabError::ConfigError(format!("{model} has no quality option")));
+        }
+        if !options.qualities.contains(&quality.as_str()) {
+            return Err(CrabError::ConfigError(format!(
+                "Invalid quality '{quality}' for {model}; supported: {}",
+                options.qualities.join(", ")
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the images to `output`, or to generated `crabai-<timestamp>`
+/// filenames in the current directory, and returns the paths. With several
+/// images, `-1`, `-2`, ... is appended to the file stem.
+pub fn save_all(
+    images: Vec<GeneratedImage>,
+    output: Option<&Path>,
+) -> Result<Vec<PathBuf>, CrabError> {
+    let count = images.len();
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    let mut paths = Vec::with_capacity(count);
+    for (i, GeneratedImage(data)) in images.into_iter().enumerate() {
+        let suffix = if count > 1 {
+            format!("-{}", i + 1)
+        } else {
+            String::new()
+        };
+        let base = output
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from(format!("crabai-{stamp}")));
+        let extension = base
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| detect_extension(&data).to_string());
+        let stem = base
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("crabai")
+            .to_string();
+        let path = base.with_file_name(format!("{stem}{suffix}.{extension}"));
+
+        std::fs::write(&path, &data)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// File extension for encoded image data, from its magic bytes.
+fn detect_extension(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\xFF\xD8\xFF") {
+        "jpg"
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        "webp"
+    } else if data.starts_with(b"GIF8") {
+        "gif"
+    } else {
+        "png"
+    }
+}
@@ -0,0 +1,329 @@
+//! Self-healing: extracts model constraints from provider error messages
+//! so they can be stored in the model cache and the failed request retried
+//! with corrected parameters.
+
+use regex::Regex;
+
+use crate::types::ModelInfo;
+
+/// A model constraint learned from a provider error message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    /// Maximum number of output tokens the model accepts.
+    MaxOutputTokens(u32),
+    /// Name of the parameter that carries the output token limit.
+    MaxTokensParam(String),
+    /// The model rejects any temperature value.
+    TemperatureUnsupported,
+    /// Inclusive range of temperatures the model accepts.
+    TemperatureRange(f32, f32),
+    /// A request parameter the model rejects outright.
+    UnsupportedParam(String),
+    /// The model does not return token log-probabilities.
+    LogprobsUnsupported,
+    /// The model does not accept image input.
+    VisionUnsupported,
+}
+
+impl Constraint {
+    /// Applies the constraint to `info`. Returns false when `info` already
+    /// reflected it, so callers can tell whether anything new was learned.
+    pub fn apply(&self, info: &mut ModelInfo) -> bool {
+        match self {
+            Constraint::MaxOutputTokens(limit) => {
+                if info.max_output_tokens == Some(*limit) {
+                    return false;
+                }
+                info.max_output_tokens = Some(*limit);
+            }
+            Constraint::MaxTokensParam(name) => {
+                if info.max_tokens_param.as_deref() == Some(name.as_str()) {
+                    return false;
+                }
+                info.max_tokens_param = Some(name.clone());
+            }
+            Constraint::TemperatureUnsupported => {
+                if !info.supports_temperature {
+                    return false;
+                }
+                info.supports_temperature = false;
+            }
+            Constraint::TemperatureRange(min, max) => {
+                if info.temperature_range == Some((*min, *max)) {
+                    return false;
+                }
+                info.temperature_range = Some((*min, *max));
+            }
+            Constraint::UnsupportedParam(name) => {
+                if info.unsupported_params.contains(name) {
+                    return false;
+                }
+                info.unsupported_params.push(name.clone());
+            }
+            Constraint::LogprobsUnsupported => {
+                if info.supports_logprobs == Some(false) {
+                    return false;
+                }
+                info.supports_logprobs = Some(false);
+            }
+            Constraint::VisionUnsupported => {
+                if info.supports_vision == Some(false) {
+                    return false;
+                }
+                info.supports_vision = Some(false);
+            }
+        }
+        let note = self.to_string();
+        if !info.learned.contains(&note) {
+            info.learned.push(note);
+        }
+        true
+    }
+}
+
+impl std::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constraint::MaxOutputTokens(limit) => write!(f, "max output tokens is {limit}"),
+            Constraint::MaxTokensParam(name) => write!(f, "token limit parameter is '{name}'"),
+            Constraint::TemperatureUnsupported => write!(f, "temperature is not supported"),
+            Constraint::TemperatureRange(min, max) => {
+                write!(f, "temperature must be between {min} and {max}")
+            }
+            Constraint::UnsupportedParam(name) => write!(f, "parameter '{name}' is not supported"),
+            Constraint::LogprobsUnsupported => write!(f, "logprobs are not supported"),
+            Constraint::VisionUnsupported => write!(f, "image input is not supported"),
+        }
+    }
+}
+
+/// Provider phrasings for a model that does not accept image input, in
+/// lowercase.
+const VISION_REJECTIONS: &[&str] = &[
+    // OpenAI "Invalid content type. image_url is only supported by certain models."
+    "image_url is only supported by",
+    // "This model does not support image input"
+    "does not support image input",
+    // Groq, Mistral "Image input is not supported for this model"
+    "image input is not supported",
+    // "Model does not support images"
+    "does not support images",
+];
+
+/// Extracts every constraint that can be recognized in a provider error message.
+pub fn extract(message: &str) -> Vec<Constraint> {
+    let mut found = Vec::new();
+    let lower = message.to_lowercase();
+
+    let param = try_extract_param_name(message);
+    if let Some(name) = &param {
+        found.push(Constraint::MaxTokensParam(name.clone()));
+    }
+
+    if lower.contains("temperature") {
+        if let Some((min, max)) = try_extract_temperature_range(message) {
+            found.push(Constraint::TemperatureRange(min, max));
+        } else if lower.contains("not supported")
+            || lower.contains("unsupported")
+            || lower.contains("does not support")
+        {
+            found.push(Constraint::TemperatureUnsupported);
+        }
+    } else if lower.contains("token") {
+        if let Some(limit) = try_extract_limit(message) {
+            found.push(Constraint::MaxOutputTokens(limit));
+        }
+    }
+
+    let unsupported = try_extract_unsupported_param(message);
+    let logprobs_param = unsupported
+        .as_deref()
+        .is_some_and(|name| name.ends_with("logprobs"));
+    let logprobs_rejected = lower.contains("logprobs")
+        && (lower.contains("not supported")
+            || lower.contains("unsupported")
+            || lower.contains("does not support")
+            || lower.contains("not enabled"));
+    if logprobs_param || logprobs_rejected {
+        // Logprobs are an explicit user request, so they are recorded as
+        // unsupported instead of being dropped from later requests.
+        found.push(Constraint::LogprobsUnsupported);
+    } else if let Some(name) = unsupported.filter(|name| !name.starts_with("image")) {
+        let is_token_param = name.contains("max_tokens") || name.contains("max_completion_tokens");
+        // Temperature is tracked by supports_temperature, and a token parameter
+        // with a named replacement is renamed rather than dropped.
+        if name != "temperature" && !(is_token_param && param.is_some()) {
+            found.push(Constraint::UnsupportedParam(name));
+        }
+    }
+
+    // Only phrasings that reject image input as such: "image generation is
+    // not supported" or an unsupported image_detail value says nothing
+    // about the model's vision support.
+    if VISION_REJECTIONS.iter().any(|phrase| lower.contains(phrase)) {
+        found.push(Constraint::VisionUnsupported);
+    }
+
+    found
+}
+
+fn try_extract_limit(message: &str) -> Option<u32> {
+    // Case 1: Anthropic "64000 > 8192"
+    if let Some(caps) = Regex::new(r"(\d+)\s*>\s*(\d+)")
+        .ok()
+        .and_then(|re| re.captures(message))
+    {
+        return caps.get(2).and_then(|m| m.as_str().parse().ok());
+    }
+
+    // Case 2: OpenAI or others "limit of 4096"
+    if let Some(caps) = Regex::new(r"(?i)limit of (\d+)")
+        .ok()
+        .and_then(|re| re.captures(message))
+    {
+        return caps.get(1).and_then(|m| m.as_str().parse().ok());
+    }
+
+    // Case 3: "maximum allowed is 8192"
+    if let Some(caps) = Regex::new(r"(?i)maximum(?: allowed)? (?:is )?(\d+)")
+        .ok()
+        .and_then(|re| re.captures(message))
+    {
+        return caps.get(1).and_then(|m| m.as_str().parse().ok());
+    }
+
+    // Case 4: OpenAI "supports at most 16384 completion tokens"
+    if let Some(caps) = Regex::new(r"(?i)at most (\d+)")
+        .ok()
+        .and_then(|re| re.captures(message))
+    {
+        return caps.get(1).and_then(|m| m.as_str().parse().ok());
+    }
+
+    None
+}
+
+fn try_extract_param_name(message: &str) -> Option<String> {
+    // Case: "Use 'max_completion_tokens' instead."
+    let re = Regex::new(r"(?i)use\s+'([^']+)'\s+instead").ok()?;
+    re.captures(message)
+        .and_then(|cap| cap.get(1))
+        .map(|m| m.as_str().to_string())
+}
+
+fn try_extract_temperature_range(message: &str) -> Option<(f32, f32)> {
+    let number = |caps: &regex::Captures, i: usize| caps.get(i)?.as_str().parse::<f32>().ok();
+
+    // Case 1: OpenAI "Only the default (1) value is supported."
+    if let Some(caps) = Regex::new(r"(?i)only the default \(([\d.]+)\) value")
+        .ok()?
+        .captures(message)
+    {
+        let value = number(&caps, 1)?;
+        return Some((value, value));
+    }
+
+    // Case 2: Google "supported range is from 0 (inclusive) to 2.0001 (exclusive)"
+    if let Some(caps) = Regex::new(r"(?i)range is from ([\d.]+)\D+?([\d.]+)")
+        .ok()?
+        .captures(message)
+    {
+        let max = number(&caps, 2)?;
+        // An exclusive bound like 2.0001 means "up to 2".
+        return Some((number(&caps, 1)?, (max * 100.0).floor() / 100.0));
+    }
+
+    // Case 3: "between 0 and 1", "range: 0..1", "[0, 2]"
+    if let Some(caps) =
+        Regex::new(r"(?i)(?:between|range:?|\[)\s*([\d.]+)\s*(?:and|to|\.\.|,)\s*([\d.]+)")
+            .ok()?
+            .captures(message)
+    {
+        return Some((number(&caps, 1)?, number(&caps, 2)?));
+    }
+
+    // Case 4: Pydantic-style "Input should be less than or equal to 1"
+    if let Some(caps) = Regex::new(r"(?i)less than or equal to ([\d.]+)")
+        .ok()?
+        .captures(message)
+    {
+        return Some((0.0, number(&caps, 1)?));
+    }
+
+    None
+}
+
+fn try_extract_unsupported_param(message: &str) -> Option<String> {
+    let patterns = [
+        // OpenAI "Unsupported parameter: 'stop' is not supported with this model."
+        r"(?i)unsupported parameter:?\s*'([^']+)'",
+        // "'stop' is not supported with this model"
+        r"(?i)'([A-Za-z_]+)' is not supported",
+        // Azure-style "Unrecognized request argument supplied: stop"
+        r"(?i)unrecognized request arguments? supplied:?\s*([A-Za-z_]+)",
+        // Pydantic-style "stop: Extra inputs are not permitted"
+        r"(?i)([A-Za-z_]+)\W+extra inputs are not permitted",
+        // "Unknown parameter: 'stop'"
+        r#"(?i)unknown (?:parameter|field|name)[:\s]+[`'"]?([A-Za-z_.]+)"#,
+    ];
+    patterns.iter().find_map(|p| {
+        Regex::new(p)
+            .ok()?
+            .captures(message)
+            .and_then(|caps| caps.get(1))
+            .map(|m| m.as_str().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_output_token_limit() {
+        let found = extract("max_tokens: 100000 > 64000, which is the maximum allowed");
+        assert_eq!(found, vec![Constraint::MaxOutputTokens(64000)]);
+    }
+
+    #[test]
+    fn extracts_max_tokens_param_rename() {
+        let found = extract(
+            "Unsupported parameter: 'max_tokens' is not supported with this model. \
+             Use 'max_completion_tokens' instead.",
+        );
+        assert_eq!(
+            found,
+            vec![Constraint::MaxTokensParam("max_completion_tokens".to_string())]
+        );
+    }
+
+    #[test]
+    fn extracts_temperature_constraints() {
+        assert_eq!(
+            extract("temperature: range: 0..1"),
+            vec![Constraint::TemperatureRange(0.0, 1.0)]
+        );
+        assert_eq!(
+            extract("Unsupported value: 'temperature' does not support 0.7 with this model."),
+            vec![Constraint::TemperatureUnsupported]
+        );
+    }
+
+    #[test]
+    fn extracts_vision_rejections() {
+        let found = extract("Invalid content type. image_url is only supported by certain models.");
+        assert!(found.contains(&Constraint::VisionUnsupported));
+        let found = extract("Image input is not supported for this model");
+        assert!(found.contains(&Constraint::VisionUnsupported));
+    }
+
+    #[test]
+    fn ignores_unrelated_image_errors() {
+        for message in [
+            "Image generation is not supported",
+            "Invalid value for image_detail: 'ultra' is not supported",
+        ] {
+            assert!(!extract(message).contains(&Constraint::VisionUnsupported), "{message}");
+        }
+    }
+}
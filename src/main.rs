@@ -1,15 +1,28 @@
+mod api_key;
+mod attachment;
+mod audio;
+mod batch;
 mod bundled_prompts;
+mod cassette;
 mod cli;
 mod config;
 mod config_editor;
+mod continuation;
+mod embed;
 mod error;
+mod fim;
+mod image;
+mod learning;
 mod model_cache;
+mod output;
 mod prompt_loader;
 mod providers;
+mod rate_limit;
 mod types;
 
-use std::io::Read;
+use std::io::{Read, Write};
 use std::process;
+use std::time::Duration;
 
 use clap::Parser;
 use console::style;
@@ -25,10 +38,16 @@ use providers::{get_provider_with_config, list_provider_names};
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    let verbose = cli.verbose;
 
-    if let Err(e) = run(cli).await {
+    let result = run(cli).await;
+    let _ = rate_limit::save();
+    if verbose {
+        print_key_rotations();
+    }
+    if let Err(e) = result {
         eprintln!("{} {e}", style("Error:").red().bold());
-        process::exit(1);
+        process::exit(e.exit_code());
     }
 }
 
@@ -39,7 +58,28 @@ async fn run(cli: Cli) -> Result<(), CrabError> {
         return config_editor::run_interactive_config(cli.use_config.as_deref()).await;
     }
 
-    let config = Config::load(cli.use_config.as_deref())?;
+    let mut config = Config::load(cli.use_config.as_deref())?;
+    let routing = openrouter_routing(&cli);
+    if let Some(routing) = routing.clone() {
+        let ignored = config
+            .advanced
+            .get_or_insert_with(Default::default)
+            .openrouter
+            .get_or_insert_with(Default::default)
+            .merge(routing);
+        if let Some(flag) = ignored {
+            eprintln!(
+                "{} {flag} ignored: the config file sets data_collection = \"deny\".",
+                style("Warning:").yellow().bold()
+            );
+        }
+    }
+
+    if let Some(dir) = &cli.record {
+        cassette::set_mode(cassette::Mode::Record(dir.clone()));
+    } else if let Some(dir) = &cli.replay {
+        cassette::set_mode(cassette::Mode::Replay(dir.clone()));
+    }
 
     // Auto-install bundled prompts if any are missing
     let prompts_dir = config.prompts_dir();
@@ -60,22 +100,31 @@ async fn run(cli: Cli) -> Result<(), CrabError> {
         return list_models(&cli, &config).await;
     }
 
+    if cli.batch_submit || cli.batch_status.is_some() || cli.batch_fetch.is_some() {
+        return run_batch(&cli, &config).await;
+    }
+
+    if cli.embed {
+        return run_embed(&cli, &config).await;
+    }
+
+    if cli.speak {
+        return run_speech(&cli, &config).await;
+    }
+
+    // Transcribe --audio first; the transcript takes the place of STDIN.
+    let transcript = match &cli.audio {
+        Some(path) => Some(transcribe_audio(&cli, &config, path).await?),
+        None => None,
+    };
+    if cli.transcript_only {
+        print!("{}", transcript.unwrap_or_default());
+        return Ok(());
+    }
+
     // Extract provider and model from --model flag or config
-    let (provider_name, model_name) = match cli.model.or(config.default_model.clone()) {
-        Some(model_str) => {
-            if model_str.contains(':') {
-                let parts: Vec<&str> = model_str.split(':').collect();
-                (parts[0].to_string(), parts[1].to_string())
-            } else {
-                let provider = config.default_provider.clone().ok_or_else(|| {
-                    CrabError::ConfigError(
-                        "No provider specified in model and no default provider in config."
-                            .to_string(),
-                    )
-                })?;
-                (provider, model_str)
-            }
-        }
+    let (provider_name, model_name) = match cli.model.clone().or(config.default_model.clone()) {
+        Some(model_str) => parse_model_spec(&model_str, &config)?,
         None => {
             let theme = ColorfulTheme::default();
             eprintln!("{}", style("No model configured.").yellow().bold());
@@ -96,18 +145,23 @@ async fn run(cli: Cli) -> Result<(), CrabError> {
         }
     };
 
+    if routing.is_some() && provider_name != "openrouter" {
+        return Err(CrabError::Unsupported(format!(
+            "OpenRouter routing options do not apply to {provider_name}"
+        )));
+    }
+
     let provider = get_provider_with_config(&provider_name, &config)?;
 
+    if cli.fim {
+        return run_fim(&cli, &config, provider.as_ref(), &model_name).await;
+    }
+
     // Handle prompt assembly
-    let (prompt_content, remaining_args) = if !cli.args.is_empty() {
-        let prompts_dir = config.prompts_dir();
-        let first_arg = &cli.args[0];
-        match prompt_loader::load_prompt(first_arg, &prompts_dir) {
-            Ok(content) => (content, &cli.args[1..]),
-            Err(_) => (first_arg.to_string(), &cli.args[1..]),
-        }
+    let (template, remaining_args) = if !cli.args.is_empty() {
+        (load_template(&cli.args[0], &config), &cli.args[1..])
     } else {
-        ("".to_string(), &[][..])
+        (prompt_loader::PromptTemplate::literal(""), &[][..])
     };
 
     let stdin_content = if !atty::is(atty::Stream::Stdin) {
@@ -117,17 +171,33 @@ async fn run(cli: Cli) -> Result<(), CrabError> {
     } else {
         None
     };
+    // Piped text, if any, comes before the transcript.
+    let stdin_content = match (stdin_content, transcript) {
+        (Some(stdin), Some(transcript)) if !stdin.trim().is_empty() => {
+            Some(format!("{stdin}\n\n{transcript}"))
+        }
+        (stdin, transcript) => transcript.or(stdin),
+    };
 
     let final_prompt =
-        prompt_loader::assemble(&prompt_content, remaining_args, stdin_content.as_deref());
+        prompt_loader::assemble(&template.body, remaining_args, stdin_content.as_deref());
+    let attachments = cli
+        .attach
+        .iter()
+        .map(|path| attachment::load(path))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    if final_prompt.trim().is_empty() {
+    if final_prompt.trim().is_empty() && attachments.is_empty() {
         return Err(CrabError::ConfigError(
             "Prompt is empty. Provide a prompt as an argument or pipe content from stdin."
                 .to_string(),
         ));
     }
 
+    if cli.image {
+        return run_image(&cli, &config, provider.as_ref(), &model_name, &final_prompt).await;
+    }
+
     let config_dir = Config::config_dir();
     let mut cache = ModelCache::load(&config_dir);
     let ttl = config.cache_ttl_hours();
@@ -146,39 +216,38 @@ async fn run(cli: Cli) -> Result<(), CrabError> {
     let temperature = cli
         .temperature
         .unwrap_or_else(|| config.resolve_temperature());
+    let max_tokens = resolve_max_tokens(&cli, &config, model_info.as_ref());
 
-    let max_tokens = match cli.max_tokens {
-        Some(s) => {
-            if s.to_lowercase() == "max" {
-                model_info
-                    .as_ref()
-                    .and_then(|m| m.max_output_tokens)
-                    .unwrap_or(4096)
-            } else {
-                s.parse::<u32>()
-                    .unwrap_or_else(|_| config.resolve_max_tokens())
-            }
-        }
-        None => config.resolve_max_tokens(),
-    };
+    // Assistant prefill: the CLI option overrides the template's front matter.
+    let prefill = normalize_prefill(cli.prefill.clone().or(template.prefill));
 
-    let (mut final_temperature, mut final_max_tokens) =
-        provider.sanitize_params(&model_name, temperature, max_tokens);
+    let mut message = types::ChatMessage::user(&final_prompt);
+    message.attachments = attachments;
+    let base_request = types::ChatRequest {
+        model: model_name.clone(),
+        messages: vec![message],
+        temperature: Some(temperature),
+        max_tokens,
+        max_tokens_key: None,
+        omit_params: Vec::new(),
+        prefill: prefill.clone(),
+        logprobs: cli.logprobs,
+        web_search: cli.web,
+    };
 
-    // Apply cached info if it exists and contradicts/enriches
-    if let Some(info) = &model_info {
-        if !info.supports_temperature {
-            final_temperature = None;
-        }
-        if let Some(limit) = info.max_output_tokens {
-            final_max_tokens = final_max_tokens.min(limit);
-        }
-    }
+    // What the provider knows about the model, narrowed by what the cache
+    // learned. Unsupported options fail here, before anything is sent.
+    let capabilities = provider
+        .model_capabilities(&model_name)
+        .learned(model_info.as_ref());
+    check_capabilities(provider.as_ref(), &capabilities, &base_request, cli.temperature)?;
+    let request = apply_model_constraints(&capabilities, model_info.as_ref(), &base_request);
 
     if cli.verbose {
         eprintln!("{}", style("Request Metadata:").yellow().bold());
         eprintln!("  {} {}", style("Provider:").cyan(), provider.name());
         eprintln!("  {} {}", style("Model:").cyan(), model_name);
+        print_key_sources(&config, &provider_name);
         eprintln!(
             "  {} {}",
             style("Temperature:").cyan(),
@@ -194,81 +263,802 @@ async fn run(cli: Cli) -> Result<(), CrabError> {
                 eprintln!("  {} {}", style("Model limit:").cyan(), limit);
             }
         }
+        eprintln!(
+            "  {} {}",
+            style("Capabilities:").cyan(),
+            capabilities.features().join(", ")
+        );
         eprintln!();
     }
 
-    let response_result = provider
-        .send(
-            &model_name,
-            &final_prompt,
-            final_temperature,
-            final_max_tokens,
-            model_info.as_ref().and_then(|m| m.max_tokens_param.clone()),
-        )
-        .await;
-
-    match response_result {
-        Ok(response) => {
-            print!("{response}");
-            Ok(())
-        }
+    let count = cli.count;
+    let sent = providers::send_many(provider.as_ref(), &request, count).await;
+    let (request, completions) = match sent {
+        Ok(completions) => (request, completions),
         Err(e) => {
-            // Extract model constraints from provider error messages to update local cache.
-            if let CrabError::ProviderError { message, .. } = &e {
-                let mut updated = false;
-                let mut info = model_info.unwrap_or_else(|| types::ModelInfo::new(&model_name));
-
-                if let Some(new_limit) = try_extract_limit(message) {
-                    if cli.verbose {
-                        eprintln!("Learning: Detected new token limit for {model_name}: {new_limit}");
-                    }
-                    info.max_output_tokens = Some(new_limit);
-                    updated = true;
-                }
+            // Learn model constraints from the error message, update the local
+            // cache, and retry once with the corrected parameters.
+            let mut info = model_info.unwrap_or_else(|| types::ModelInfo::new(&model_name));
+            if !learn_from_error(&e, &mut info, cli.verbose) {
+                return Err(e);
+            }
+            cache.update_model(&provider_name, info.clone());
+            let _ = cache.save(&config_dir);
 
-                if let Some(new_param) = try_extract_param_name(message) {
-                    if cli.verbose {
-                        eprintln!("Learning: Detected new token parameter name for {model_name}: {new_param}");
+            // Dropping an option (logprobs, attachments) would not give the
+            // user what they asked for.
+            let capabilities = provider
+                .model_capabilities(&model_name)
+                .learned(Some(&info));
+            check_capabilities(provider.as_ref(), &capabilities, &base_request, cli.temperature)?;
+
+            let retry = apply_model_constraints(&capabilities, Some(&info), &base_request);
+            if cli.verbose {
+                eprintln!("Retrying with corrected parameters...");
+            }
+            match providers::send_many(provider.as_ref(), &retry, count).await {
+                Ok(completions) => (retry, completions),
+                Err(e) => {
+                    // Keep whatever the second failure teaches for next time.
+                    if learn_from_error(&e, &mut info, cli.verbose) {
+                        cache.update_model(&provider_name, info);
+                        let _ = cache.save(&config_dir);
                     }
-                    info.max_tokens_param = Some(new_param);
-                    updated = true;
+                    return Err(e);
                 }
+            }
+        }
+    };
 
-                if updated {
-                    cache.update_model(&provider_name, info);
-                    let _ = cache.save(&config_dir);
-                }
+    let mut rounds = 0;
+    let mut answers = Vec::with_capacity(completions.len());
+    for mut completion in completions {
+        // The model's answer continues the prefill; restore the full text so
+        // downstream tools (and continuation) see the complete answer.
+        if let Some(prefill) = &prefill {
+            completion.text.insert_str(0, prefill);
+        }
+
+        let completion = match cli.continue_on_truncate {
+            Some(max_rounds) if completion.is_truncated() => {
+                let continued = continuation::continue_truncated(
+                    provider.as_ref(),
+                    &request,
+                    completion,
+                    max_rounds,
+                )
+                .await?;
+                rounds += continued.rounds;
+                continued.completion
             }
-            Err(e)
+            _ => completion,
+        };
+        answers.push(completion);
+    }
+
+    if cli.verbose {
+        let reasons: Vec<String> = answers
+            .iter()
+            .filter_map(|c| c.finish_reason.as_ref().map(|r| r.to_string()))
+            .collect();
+        if !reasons.is_empty() {
+            eprintln!("{} {}", style("Finish reason:").cyan(), reasons.join(", "));
+        }
+        if cli.continue_on_truncate.is_some() {
+            eprintln!("{} {}", style("Continuation rounds:").cyan(), rounds);
+        }
+        let ids: Vec<&str> = answers.iter().filter_map(|c| c.id.as_deref()).collect();
+        if !ids.is_empty() {
+            eprintln!("{} {}", style("Generation id:").cyan(), ids.join(", "));
+        }
+        let mut upstream: Vec<&str> = answers
+            .iter()
+            .filter_map(|c| c.upstream_provider.as_deref())
+            .collect();
+        upstream.dedup();
+        if !upstream.is_empty() {
+            eprintln!("{} {}", style("Served by:").cyan(), upstream.join(", "));
         }
+        let tokens: Vec<u32> = answers.iter().filter_map(|c| c.output_tokens).collect();
+        if !tokens.is_empty() {
+            eprintln!("{} {}", style("Output tokens:").cyan(), tokens.iter().sum::<u32>());
+        }
+        if let Some(quota) = rate_limit::last_observed(&provider_name) {
+            eprintln!("{} {}", style("Remaining quota:").cyan(), quota.summary());
+        }
+    }
+
+    let truncated = answers.iter().filter(|c| c.is_truncated()).count();
+    if truncated > 0 {
+        let subject = if answers.len() == 1 {
+            "Answer".to_string()
+        } else {
+            format!("{truncated} of {} answers", answers.len())
+        };
+        let hint = if cli.continue_on_truncate.is_some() {
+            format!("still truncated after {rounds} continuation rounds")
+        } else {
+            "truncated by the output token limit; use --continue-on-truncate to resume it".to_string()
+        };
+        eprintln!("{} {subject} {hint}.", style("Warning:").yellow().bold());
+    }
+
+    // A single answer is printed as before; several become a JSON array.
+    let as_document = |docs: Vec<serde_json::Value>| match <[_; 1]>::try_from(docs) {
+        Ok([doc]) => doc,
+        Err(docs) => serde_json::Value::Array(docs),
+    };
+
+    if cli.json {
+        let docs = answers
+            .iter()
+            .map(|c| output::completion_json(&provider_name, &model_name, c))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&as_document(docs))?);
+        return Ok(());
     }
+
+    if cli.logprobs.is_some() {
+        if answers.iter().all(|c| c.logprobs.is_some()) {
+            let docs = answers
+                .iter()
+                .filter_map(|c| c.logprobs.as_deref())
+                .map(|logprobs| output::logprobs_json(&provider_name, &model_name, logprobs))
+                .collect();
+            eprintln!("{}", serde_json::to_string_pretty(&as_document(docs))?);
+        } else {
+            eprintln!(
+                "{} The provider returned no token log-probabilities.",
+                style("Warning:").yellow().bold()
+            );
+        }
+    }
+
+    let separator = output::unescape(&cli.separator);
+    let texts: Vec<String> = answers
+        .iter()
+        .map(|c| format!("{}{}", c.text, output::references(&c.sources)))
+        .collect();
+    print!("{}", texts.join(&separator));
+    Ok(())
 }
 
-fn try_extract_limit(message: &str) -> Option<u32> {
-    // Case 1: Anthropic "64000 > 8192"
-    if let Some(caps) = regex::Regex::new(r"(\d+)\s*>\s*(\d+)").ok().and_then(|re| re.captures(message)) {
-        return caps.get(2).and_then(|m| m.as_str().parse().ok());
+/// Loads the prompt template `name` from the prompts directory, or treats
+/// `name` as a literal prompt if no such template exists.
+fn load_template(name: &str, config: &Config) -> prompt_loader::PromptTemplate {
+    match prompt_loader::load_prompt(name, &config.prompts_dir()) {
+        Ok(content) => prompt_loader::parse_template(&content),
+        Err(_) => prompt_loader::PromptTemplate::literal(name),
     }
+}
+
+/// Trims trailing whitespace from a prefill, because several APIs reject
+/// it. An empty prefill becomes None.
+fn normalize_prefill(prefill: Option<String>) -> Option<String> {
+    prefill
+        .map(|p| p.trim_end().to_string())
+        .filter(|p| !p.is_empty())
+}
 
-    // Case 2: OpenAI or others "limit of 4096"
-    if let Some(caps) = regex::Regex::new(r"(?i)limit of (\d+)").ok().and_then(|re| re.captures(message)) {
-        return caps.get(1).and_then(|m| m.as_str().parse().ok());
+/// Resolves --max-tokens ("max" means the model's output limit) or the
+/// configured default.
+fn resolve_max_tokens(cli: &Cli, config: &Config, model_info: Option<&types::ModelInfo>) -> u32 {
+    match &cli.max_tokens {
+        Some(s) => {
+            if s.to_lowercase() == "max" {
+                model_info
+                    .and_then(|m| m.max_output_tokens)
+                    .unwrap_or(4096)
+            } else {
+                s.parse::<u32>()
+                    .unwrap_or_else(|_| config.resolve_max_tokens())
+            }
+        }
+        None => config.resolve_max_tokens(),
     }
+}
 
-    // Case 3: "maximum allowed is 8192"
-    if let Some(caps) = regex::Regex::new(r"(?i)maximum(?: allowed)? (?:is )?(\d+)").ok().and_then(|re| re.captures(message)) {
-        return caps.get(1).and_then(|m| m.as_str().parse().ok());
+/// Splits a `provider:model` spec. A bare model name uses the configured
+/// default provider.
+fn parse_model_spec(spec: &str, config: &Config) -> Result<(String, String), CrabError> {
+    if spec.contains(':') {
+        let parts: Vec<&str> = spec.split(':').collect();
+        Ok((parts[0].to_string(), parts[1].to_string()))
+    } else {
+        let provider = config.default_provider.clone().ok_or_else(|| {
+            CrabError::ConfigError(
+                "No provider specified in model and no default provider in config.".to_string(),
+            )
+        })?;
+        Ok((provider, spec.to_string()))
     }
+}
+
+/// Handles --batch-submit, --batch-status and --batch-fetch: submits jobs,
+/// reports their progress and writes their results. Submitted jobs are
+/// tracked in `batches.json` in the config directory.
+async fn run_batch(cli: &Cli, config: &Config) -> Result<(), CrabError> {
+    let config_dir = Config::config_dir();
+    let mut store = batch::BatchStore::load(&config_dir);
+
+    if cli.batch_submit {
+        let Some((prompt, inputs)) = cli.args.split_first() else {
+            return Err(CrabError::ConfigError(
+                "No prompt. Usage: crabai --batch-submit PROMPT [FILE]...".to_string(),
+            ));
+        };
+        let inputs: Vec<std::path::PathBuf> = inputs.iter().map(Into::into).collect();
+        let listing = if inputs.is_empty() && !atty::is(atty::Stream::Stdin) {
+            let mut buf = String::new();
+            std::io::stdin().read_to_string(&mut buf)?;
+            Some(buf)
+        } else {
+            None
+        };
+        let inputs = batch::input_paths(&inputs, listing.as_deref());
+        if inputs.is_empty() {
+            return Err(CrabError::ConfigError(
+                "No inputs. Pass input files or pipe a list of paths into stdin.".to_string(),
+            ));
+        }
+        let contents = batch::read_inputs(&inputs)?;
 
-    None
+        let spec = cli.model.clone().or(config.default_model.clone()).ok_or_else(|| {
+            CrabError::ConfigError(
+                "No model specified. Use -m <provider:model> or set default_model."
+                    .to_string(),
+            )
+        })?;
+        let (provider_name, model_name) = parse_model_spec(&spec, config)?;
+        let provider = get_provider_with_config(&provider_name, config)?;
+
+        let template = load_template(prompt, config);
+        let prefill = normalize_prefill(template.prefill);
+
+        let model_info = if config.model_cache_enabled() {
+            let mut cache = ModelCache::load(&config_dir);
+            get_models(&provider_name, &mut cache, config.cache_ttl_hours(), true, config)
+                .await
+                .ok()
+                .and_then(|models| models.into_iter().find(|m| m.id == model_name))
+        } else {
+            None
+        };
+        let temperature = cli
+            .temperature
+            .unwrap_or_else(|| config.resolve_temperature());
+        let max_tokens = resolve_max_tokens(cli, config, model_info.as_ref());
+        let capabilities = provider
+            .model_capabilities(&model_name)
+            .learned(model_info.as_ref());
+
+        let requests = contents
+            .iter()
+            .map(|content| {
+                let base = types::ChatRequest {
+                    model: model_name.clone(),
+                    messages: vec![types::ChatMessage::user(&prompt_loader::assemble(
+                        &template.body,
+                        &[],
+                        Some(content),
+                    ))],
+                    temperature: Some(temperature),
+                    max_tokens,
+                    max_tokens_key: None,
+                    omit_params: Vec::new(),
+                    prefill: prefill.clone(),
+                    logprobs: None,
+                    web_search: false,
+                };
+                check_capabilities(provider.as_ref(), &capabilities, &base, cli.temperature)?;
+                Ok(apply_model_constraints(&capabilities, model_info.as_ref(), &base))
+            })
+            .collect::<Result<Vec<_>, CrabError>>()?;
+
+        let status = provider.submit_batch(&requests).await?;
+        store.add(batch::BatchJob {
+            id: status.id.clone(),
+            provider: provider_name.clone(),
+            model: model_name.clone(),
+            inputs,
+            prefill,
+            submitted_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        });
+        store.save(&config_dir)?;
+
+        if cli.verbose {
+            eprintln!("{}", style("Batch submitted:").yellow().bold());
+            eprintln!("  {} {}", style("Provider:").cyan(), provider.name());
+            eprintln!("  {} {}", style("Model:").cyan(), model_name);
+            print_key_sources(config, &provider_name);
+            eprintln!("  {} {}", style("Requests:").cyan(), requests.len());
+            eprintln!("  {} {}", style("Status:").cyan(), status.status);
+            eprintln!();
+        }
+        println!("{}", status.id);
+    } else if let Some(id) = &cli.batch_status {
+        let jobs = match id {
+            Some(id) => vec![store.get(id)?],
+            None => store.jobs().iter().collect(),
+        };
+        for job in jobs {
+            let provider = get_provider_with_config(&job.provider, config)?;
+            let status = provider.batch_status(&job.id).await?;
+            println!(
+                "{}\t{}:{}\t{}\t{}/{} succeeded, {} failed",
+                job.id,
+                job.provider,
+                job.model,
+                status.status,
+                status.succeeded,
+                status.total,
+                status.failed
+            );
+        }
+    } else if let Some(id) = &cli.batch_fetch {
+        let output_dir = cli.output.as_deref().unwrap_or(std::path::Path::new("."));
+        let job = store.get(id)?;
+        let provider = get_provider_with_config(&job.provider, config)?;
+        let status = provider.batch_status(id).await?;
+        if !status.finished {
+            return Err(CrabError::BatchPending(format!(
+                "{id} is {} ({}/{} done)",
+                status.status,
+                status.succeeded + status.failed,
+                status.total
+            )));
+        }
+
+        let results = provider.fetch_batch(id).await?;
+        let written = batch::write_results(job, results, output_dir)?;
+        for path in &written.paths {
+            println!("{}", path.display());
+        }
+        for (input, message) in &written.failures {
+            eprintln!("{} {}: {message}", style("Failed:").red().bold(), input.display());
+        }
+        if !written.failures.is_empty() {
+            return Err(CrabError::ProviderError {
+                provider: job.provider.clone(),
+                message: format!(
+                    "{} of {} batch requests failed",
+                    written.failures.len(),
+                    job.inputs.len()
+                ),
+            });
+        }
+    }
+    Ok(())
 }
 
-fn try_extract_param_name(message: &str) -> Option<String> {
-    // Case: "Use 'max_completion_tokens' instead."
-    let re = regex::Regex::new(r"(?i)use\s+'([^']+)'\s+instead").ok()?;
-    re.captures(message)
-        .and_then(|cap| cap.get(1))
-        .map(|m| m.as_str().to_string())
+/// Transcribes the --audio file. The model comes from --audio-model, then
+/// `default_audio_model`; otherwise the chat model's provider transcribes
+/// with its default transcription model.
+async fn transcribe_audio(
+    cli: &Cli,
+    config: &Config,
+    path: &std::path::Path,
+) -> Result<String, CrabError> {
+    let audio_spec = cli.audio_model.clone().or(config.default_audio_model.clone());
+    let (provider_name, model) = match audio_spec {
+        Some(spec) => {
+            let (provider, model) = parse_model_spec(&spec, config)?;
+            (provider, Some(model))
+        }
+        None => {
+            let spec = cli
+                .model
+                .clone()
+                .or(config.default_model.clone())
+                .ok_or_else(|| {
+                    CrabError::ConfigError(
+                        "No transcription model. Use --audio-model <provider:model> or -m."
+                            .to_string(),
+                    )
+                })?;
+            (parse_model_spec(&spec, config)?.0, None)
+        }
+    };
+    let provider = get_provider_with_config(&provider_name, config)?;
+    let request = audio::load(path, model)?;
+
+    if cli.verbose {
+        eprintln!(
+            "{} {} via {}",
+            style("Transcribing:").cyan(),
+            path.display(),
+            provider.name()
+        );
+    }
+    provider.transcribe(&request).await
+}
+
+/// Audio formats accepted by --audio-format.
+const SPEECH_FORMATS: &[&str] = &["mp3", "opus", "aac", "flac", "wav", "pcm"];
+
+/// Handles --speak: reads STDIN (or the arguments) aloud with the model from
+/// `-m` or `default_speech_model`. The audio goes to `-o`, or to STDOUT when
+/// it is not a terminal so it can be piped into a player.
+async fn run_speech(cli: &Cli, config: &Config) -> Result<(), CrabError> {
+    let spec = cli
+        .model
+        .clone()
+        .or(config.default_speech_model.clone())
+        .ok_or_else(|| {
+            CrabError::ConfigError(
+                "No speech model specified. Use -m <provider:model> or set default_speech_model."
+                    .to_string(),
+            )
+        })?;
+    let (provider_name, model_name) = parse_model_spec(&spec, config)?;
+
+    if let Some(format) = &cli.audio_format {
+        if !SPEECH_FORMATS.contains(&format.as_str()) {
+            return Err(CrabError::ConfigError(format!(
+                "Invalid audio format '{format}'; supported: {}",
+                SPEECH_FORMATS.join(", ")
+            )));
+        }
+    }
+    if let Some(speed) = cli.speed {
+        if !(0.25..=4.0).contains(&speed) {
+            return Err(CrabError::ConfigError(format!(
+                "Invalid speed {speed}; must be between 0.25 and 4.0"
+            )));
+        }
+    }
+    if cli.output.is_none() && atty::is(atty::Stream::Stdout) {
+        return Err(CrabError::ConfigError(
+            "Refusing to write audio to a terminal. Use -o <file> or pipe the output.".to_string(),
+        ));
+    }
+
+    let mut input = String::new();
+    if !atty::is(atty::Stream::Stdin) {
+        std::io::stdin().read_to_string(&mut input)?;
+    }
+    if input.trim().is_empty() {
+        input = cli.args.join(" ");
+    }
+    if input.trim().is_empty() {
+        return Err(CrabError::ConfigError(
+            "Nothing to speak. Pipe text into stdin or pass it as an argument.".to_string(),
+        ));
+    }
+
+    let provider = get_provider_with_config(&provider_name, config)?;
+    let request = types::SpeechRequest {
+        model: model_name,
+        input,
+        voice: cli.voice.clone(),
+        format: cli.audio_format.clone(),
+        speed: cli.speed,
+    };
+    let audio = provider.speak(&request).await?;
+
+    match &cli.output {
+        Some(path) => {
+            std::fs::write(path, &audio)?;
+            println!("{}", path.display());
+        }
+        None => std::io::stdout().write_all(&audio)?,
+    }
+    Ok(())
+}
+
+/// Handles --embed: prints embedding vectors for STDIN.
+///
+/// The model comes from `-m`, then `default_embed_model`, then the first
+/// embedding model in the default provider's (cached) model list.
+async fn run_embed(cli: &Cli, config: &Config) -> Result<(), CrabError> {
+    let model_spec = cli.model.clone().or(config.default_embed_model.clone());
+    let (provider_name, model_name) = match model_spec {
+        Some(spec) => parse_model_spec(&spec, config)?,
+        None => {
+            let provider_name = config
+                .default_provider
+                .clone()
+                .or_else(|| {
+                    let spec = config.default_model.as_deref()?;
+                    spec.split_once(':').map(|(p, _)| p.to_string())
+                })
+                .ok_or_else(|| {
+                    CrabError::ConfigError(
+                        "No embedding model specified. Use -m <provider:model> or set default_embed_model."
+                            .to_string(),
+                    )
+                })?;
+            let config_dir = Config::config_dir();
+            let mut cache = ModelCache::load(&config_dir);
+            let cache_enabled = config.model_cache_enabled();
+            let ttl = config.cache_ttl_hours();
+            let models = get_models(&provider_name, &mut cache, ttl, cache_enabled, config).await?;
+            if cache_enabled {
+                let _ = cache.save(&config_dir);
+            }
+            let model = models
+                .into_iter()
+                .find(|m| m.is_embedding_model())
+                .ok_or_else(|| {
+                    CrabError::ConfigError(format!(
+                        "No embedding model found for {provider_name}. Use -m <provider:model> or set default_embed_model."
+                    ))
+                })?;
+            (provider_name, model.id)
+        }
+    };
+    let provider = get_provider_with_config(&provider_name, config)?;
+
+    let mut input = String::new();
+    if !atty::is(atty::Stream::Stdin) {
+        std::io::stdin().read_to_string(&mut input)?;
+    }
+    let inputs = embed::split_inputs(&input, cli.lines);
+    if inputs.is_empty() {
+        return Err(CrabError::ConfigError(
+            "Nothing to embed. Pipe text into stdin.".to_string(),
+        ));
+    }
+
+    if cli.verbose {
+        eprintln!("{}", style("Request Metadata:").yellow().bold());
+        eprintln!("  {} {}", style("Provider:").cyan(), provider.name());
+        eprintln!("  {} {}", style("Model:").cyan(), model_name);
+        print_key_sources(config, &provider_name);
+        eprintln!("  {} {}", style("Inputs:").cyan(), inputs.len());
+        eprintln!();
+    }
+
+    let vectors = embed::embed_all(
+        provider.as_ref(),
+        &model_name,
+        &inputs,
+        cli.batch_size as usize,
+        cli.dimensions,
+    )
+    .await?;
+    print!(
+        "{}",
+        embed::format(cli.embed_format, &provider_name, &model_name, &inputs, &vectors)?
+    );
+    Ok(())
+}
+
+/// Handles --image: generates images from the assembled prompt, writes them
+/// to disk and prints one path per line.
+async fn run_image(
+    cli: &Cli,
+    config: &Config,
+    provider: &dyn providers::Provider,
+    model_name: &str,
+    prompt: &str,
+) -> Result<(), CrabError> {
+    let request = types::ImageRequest {
+        model: model_name.to_string(),
+        prompt: prompt.to_string(),
+        size: cli.size.clone(),
+        quality: cli.quality.clone(),
+        count: cli.count,
+    };
+    image::validate(&request, provider.image_options(model_name).as_ref())?;
+
+    if cli.verbose {
+        eprintln!("{}", style("Request Metadata:").yellow().bold());
+        eprintln!("  {} {}", style("Provider:").cyan(), provider.name());
+        eprintln!("  {} {}", style("Model:").cyan(), model_name);
+        print_key_sources(config, provider.name());
+        eprintln!("  {} {}", style("Images:").cyan(), request.count);
+        eprintln!();
+    }
+
+    let images = provider.generate_images(&request).await?;
+    if images.is_empty() {
+        return Err(CrabError::ProviderError {
+            provider: provider.name().to_string(),
+            message: "No images returned".to_string(),
+        });
+    }
+    for path in image::save_all(images, cli.output.as_deref())? {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// Handles --fim: completes the code between the prefix and the suffix and
+/// prints only the inserted code, so editors can shell out to crabai.
+async fn run_fim(
+    cli: &Cli,
+    config: &Config,
+    provider: &dyn providers::Provider,
+    model_name: &str,
+) -> Result<(), CrabError> {
+    let stdin_content = if !atty::is(atty::Stream::Stdin) {
+        let mut buf = String::new();
+        std::io::stdin().read_to_string(&mut buf)?;
+        Some(buf)
+    } else {
+        None
+    };
+    let (prefix, suffix) = fim::read_input(
+        cli.prefix_file.as_deref(),
+        cli.suffix_file.as_deref(),
+        stdin_content.as_deref(),
+        &cli.cursor,
+    )?;
+
+    let max_tokens = cli
+        .max_tokens
+        .as_deref()
+        .and_then(|s| s.parse::<u32>().ok())
+        .unwrap_or_else(|| config.resolve_max_tokens());
+    let temperature = provider
+        .model_capabilities(model_name)
+        .clamp_temperature(cli.temperature.unwrap_or_else(|| config.resolve_temperature()));
+    let request = types::FimRequest {
+        model: model_name.to_string(),
+        prefix,
+        suffix,
+        temperature,
+        max_tokens,
+    };
+    let completion = provider.complete_fim(&request).await?;
+
+    if cli.verbose {
+        if let Some(reason) = &completion.finish_reason {
+            eprintln!("{} {}", style("Finish reason:").cyan(), reason);
+        }
+        if let Some(tokens) = completion.output_tokens {
+            eprintln!("{} {}", style("Output tokens:").cyan(), tokens);
+        }
+    }
+
+    print!("{}", completion.text);
+    Ok(())
+}
+
+/// Prints where the provider's API keys come from, for -v. Looks them up
+/// first if needed; a failed lookup is left for the request to report.
+fn print_key_sources(config: &Config, provider: &str) {
+    let _ = config.api_key(provider);
+    for (provider, source) in api_key::sources(Some(provider)) {
+        eprintln!("  {} {provider} from {source}", style("API key:").cyan());
+    }
+    for skipped in api_key::skipped(Some(provider)) {
+        eprintln!("  {} {skipped}", style("API key:").cyan());
+    }
+}
+
+/// Prints the API key switches after rate limit or quota errors, for -v.
+fn print_key_rotations() {
+    for rotation in api_key::rotations() {
+        eprintln!("{} {rotation}", style("Key rotation:").cyan());
+    }
+}
+
+/// The OpenRouter routing options given on the command line, None if there
+/// are none.
+fn openrouter_routing(cli: &Cli) -> Option<config::OpenRouterAdvancedConfig> {
+    let routing = config::OpenRouterAdvancedConfig {
+        order: cli.provider_order.clone(),
+        allow_fallbacks: cli.no_fallbacks.then_some(false),
+        data_collection: cli.data_collection,
+        quantizations: cli.quantizations.clone(),
+        models: (!cli.fallback_models.is_empty()).then(|| cli.fallback_models.clone()),
+        transforms: cli.transforms.clone(),
+    };
+    let given = routing.order.is_some()
+        || routing.allow_fallbacks.is_some()
+        || routing.data_collection.is_some()
+        || routing.quantizations.is_some()
+        || routing.models.is_some()
+        || routing.transforms.is_some();
+    given.then_some(routing)
+}
+
+/// Fails with `Unsupported` if `request` asks for something the model cannot
+/// do. `temperature` is the one given on the command line; a configured
+/// temperature is clamped to the model's range instead.
+fn check_capabilities(
+    provider: &dyn providers::Provider,
+    capabilities: &types::Capabilities,
+    request: &types::ChatRequest,
+    temperature: Option<f32>,
+) -> Result<(), CrabError> {
+    let provider_wide = provider.capabilities();
+    // Blame the provider when none of its models has the capability.
+    let subject = |any_model: bool| {
+        if any_model {
+            format!("{}:{}", provider.name(), request.model)
+        } else {
+            provider.name().to_string()
+        }
+    };
+    let unsupported = |any_model: bool, what: &str| {
+        CrabError::Unsupported(format!("{} {what}", subject(any_model)))
+    };
+
+    if request.prefill.is_some() && !capabilities.prefill {
+        return Err(unsupported(provider_wide.prefill, "does not support assistant prefill"));
+    }
+    if request.logprobs.is_some() && !capabilities.logprobs {
+        return Err(unsupported(provider_wide.logprobs, "does not return token log-probabilities"));
+    }
+    let attachments = request.messages.iter().flat_map(|m| &m.attachments);
+    if attachments.clone().next().is_some() && !capabilities.vision {
+        return Err(unsupported(provider_wide.vision, "does not accept image or PDF attachments"));
+    }
+    if attachments.clone().any(|a| a.is_pdf()) && !capabilities.pdf {
+        return Err(unsupported(provider_wide.pdf, "does not accept PDF attachments"));
+    }
+    if request.web_search && !capabilities.web_search {
+        return Err(unsupported(provider_wide.web_search, "does not support web search"));
+    }
+    if let Some(t) = temperature {
+        match capabilities.temperature {
+            None => {
+                let any_model = provider_wide.temperature.is_some();
+                return Err(unsupported(any_model, "does not accept a temperature"));
+            }
+            Some((min, max)) if !(min..=max).contains(&t) => {
+                let what = if min == max {
+                    format!("only accepts temperature {min}")
+                } else {
+                    format!("accepts temperatures from {min} to {max}")
+                };
+                return Err(unsupported(true, &what));
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the final request parameters from the CLI/config values in
+/// `base`, the temperature range of `capabilities`, and the cached model
+/// limits.
+fn apply_model_constraints(
+    capabilities: &types::Capabilities,
+    model_info: Option<&types::ModelInfo>,
+    base: &types::ChatRequest,
+) -> types::ChatRequest {
+    let mut final_max_tokens = base.max_tokens;
+    if let Some(limit) = model_info.and_then(|info| info.max_output_tokens) {
+        final_max_tokens = final_max_tokens.min(limit);
+    }
+
+    types::ChatRequest {
+        temperature: base
+            .temperature
+            .and_then(|t| capabilities.clamp_temperature(t)),
+        max_tokens: final_max_tokens,
+        max_tokens_key: model_info.and_then(|m| m.max_tokens_param.clone()),
+        omit_params: model_info
+            .map(|m| m.unsupported_params.clone())
+            .unwrap_or_default(),
+        ..base.clone()
+    }
+}
+
+/// Extracts model constraints from a provider error into `info`.
+/// Returns true if anything new was learned.
+fn learn_from_error(error: &CrabError, info: &mut types::ModelInfo, verbose: bool) -> bool {
+    let Some(message) = error.constraint_message() else {
+        return false;
+    };
+
+    let mut updated = false;
+    for constraint in learning::extract(message) {
+        if constraint.apply(info) {
+            if verbose {
+                eprintln!("Learning: {} for {}", constraint, info.id);
+            }
+            updated = true;
+        }
+    }
+    updated
 }
 
 /// Handles the --list-models command.
@@ -279,19 +1069,40 @@ async fn list_models(cli: &Cli, config: &Config) -> Result<(), CrabError> {
     let cache_enabled = config.model_cache_enabled();
 
     let mut all_models = Vec::new();
-    for name in list_provider_names() {
-        let models = get_models(name, &mut cache, ttl, cache_enabled, config).await;
+    let mut to_fetch = Vec::new();
+    let mut skipped = Vec::new();
+    for name in list_provider_names(config) {
+        let cached = cache_enabled.then(|| cache.get(&name, ttl)).flatten();
+        if let Some(models) = cached {
+            all_models.extend(models.iter().map(|m| format!("{name}:{}", m.id)));
+        } else if needs_api_key(&name)
+            && matches!(config.api_key_sources(&name), Ok(keys) if !keys.is_configured())
+        {
+            skipped.push(name);
+        } else {
+            to_fetch.push(name);
+        }
+    }
+
+    // Providers are queried concurrently, so one slow or unreachable
+    // provider only costs its own timeout.
+    let timeout = Duration::from_secs(config.list_models_timeout_secs());
+    let results = futures::future::join_all(to_fetch.into_iter().map(|name| async move {
+        let models = fetch_models(&name, config, timeout).await;
+        (name, models)
+    }))
+    .await;
+
+    let mut failures = Vec::new();
+    for (name, models) in results {
         match models {
             Ok(models) => {
-                for m in models {
-                    all_models.push(format!("{name}:{}", m.id));
-                }
-            }
-            Err(e) => {
-                if cli.verbose {
-                    eprintln!("{} {name}: {e}", style("Warning:").yellow().bold());
+                all_models.extend(models.iter().map(|m| format!("{name}:{}", m.id)));
+                if cache_enabled {
+                    cache.set(&name, models);
                 }
             }
+            Err(e) => failures.push((name, e)),
         }
     }
 
@@ -299,6 +1110,25 @@ async fn list_models(cli: &Cli, config: &Config) -> Result<(), CrabError> {
         let _ = cache.save(&config_dir);
     }
 
+    if cli.verbose {
+        for (provider, source) in api_key::sources(None) {
+            eprintln!("{} {provider} from {source}", style("API key:").cyan());
+        }
+        for skipped in api_key::skipped(None) {
+            eprintln!("{} {skipped}", style("API key:").cyan());
+        }
+    }
+    if cli.verbose && !skipped.is_empty() {
+        eprintln!(
+            "{} {}",
+            style("Skipped (no API key):").cyan(),
+            skipped.join(", ")
+        );
+    }
+    for (name, e) in &failures {
+        eprintln!("{} {name}: {e}", style("Warning:").yellow().bold());
+    }
+
     if all_models.is_empty() {
         return Err(CrabError::ConfigError(
             "Could not fetch any models. Check your API keys and network connection.".to_string(),
@@ -328,6 +1158,29 @@ async fn list_models(cli: &Cli, config: &Config) -> Result<(), CrabError> {
     Ok(())
 }
 
+/// Whether `provider` is a built-in provider that needs an API key. Plugins
+/// handle authentication themselves.
+fn needs_api_key(provider: &str) -> bool {
+    provider
+        .parse::<types::ProviderName>()
+        .is_ok_and(|p| p != types::ProviderName::Mock)
+}
+
+/// Fetches a provider's model list, giving up after `timeout`.
+async fn fetch_models(
+    provider_name: &str,
+    config: &Config,
+    timeout: Duration,
+) -> Result<Vec<types::ModelInfo>, CrabError> {
+    let provider = get_provider_with_config(provider_name, config)?;
+    tokio::time::timeout(timeout, provider.list_models())
+        .await
+        .map_err(|_| CrabError::ProviderError {
+            provider: provider_name.to_string(),
+            message: format!("no model list within {}s", timeout.as_secs()),
+        })?
+}
+
 /// Retrieves model list for a provider, using cache if available and valid.
 async fn get_models(
     provider_name: &str,
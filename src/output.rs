@@ -0,0 +1,93 @@
+//! Output formatting: the JSON document printed by --json, the token
+//! log-probability report written to STDERR for --logprobs, the source
+//! list printed after web-grounded answers, and the separator printed
+//! between several answers.
+
+use serde_json::{json, Value};
+
+use crate::types::{Completion, Source, TokenLogprob};
+
+/// Full result of a request, printed on STDOUT with --json.
+pub fn completion_json(provider: &str, model: &str, completion: &Completion) -> Value {
+    let mut doc = json!({
+        "provider": provider,
+        "model": model,
+        "text": completion.text,
+        "finish_reason": completion.finish_reason.as_ref().map(|r| r.to_string()),
+        "output_tokens": completion.output_tokens,
+    });
+    if let Some(logprobs) = &completion.logprobs {
+        doc["logprobs"] = tokens_json(logprobs);
+    }
+    if !completion.sources.is_empty() {
+        doc["sources"] = json!(completion.sources);
+    }
+    if let Some(id) = &completion.id {
+        doc["id"] = json!(id);
+    }
+    if let Some(upstream) = &completion.upstream_provider {
+        doc["upstream_provider"] = json!(upstream);
+    }
+    doc
+}
+
+/// Numbered list of the web sources of an answer, printed after it.
+/// Empty when there are none.
+pub fn references(sources: &[Source]) -> String {
+    if sources.is_empty() {
+        return String::new();
+    }
+    let lines: Vec<String> = sources
+        .iter()
+        .enumerate()
+        .map(|(i, source)| match &source.title {
+            Some(title) => format!("[{}] {title} - {}", i + 1, source.url),
+            None => format!("[{}] {}", i + 1, source.url),
+        })
+        .collect();
+    format!("\n\nSources:\n{}", lines.join("\n"))
+}
+
+/// Token log-probability report, written to STDERR when --logprobs is used
+/// without --json.
+pub fn logprobs_json(provider: &str, model: &str, logprobs: &[TokenLogprob]) -> Value {
+    json!({
+        "provider": provider,
+        "model": model,
+        "logprobs": tokens_json(logprobs),
+    })
+}
+
+/// Each token with its log-probability, the matching probability, and the
+/// alternatives considered at that position.
+fn tokens_json(logprobs: &[TokenLogprob]) -> Value {
+    logprobs
+        .iter()
+        .map(|t| {
+            let mut token = json!({
+                "token": t.token,
+                "logprob": t.logprob,
+                "probability": t.logprob.exp(),
+            });
+            if !t.top_logprobs.is_empty() {
+                token["top_logprobs"] = t
+                    .top_logprobs
+                    .iter()
+                    .map(|alt| {
+                        json!({
+                            "token": alt.token,
+                            "logprob": alt.logprob,
+                            "probability": alt.logprob.exp(),
+                        })
+                    })
+                    .collect();
+            }
+            token
+        })
+        .collect()
+}
+
+/// Expands the `\n` and `\t` escapes in a separator given on the command line.
+pub fn unescape(separator: &str) -> String {
+    separator.replace("\\n", "\n").replace("\\t", "\t")
+}
@@ -11,6 +11,77 @@ pub fn load_prompt(name: &str, prompts_dir: &Path) -> Result<String, CrabError>
     Ok(std::fs::read_to_string(&path)?)
 }
 
+/// A prompt template: the prompt text plus options from its front matter.
+///
+/// Front matter is an optional block of `key: value` lines at the very start
+/// of the file, between two `---` lines:
+///
+/// ```text
+/// ---
+/// prefill: "{"
+/// ---
+/// Extract the fields below as JSON...
+/// ```
+///
+/// Values may be JSON strings (to allow escapes such as `\n`) or plain
+/// text. Unknown keys are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct PromptTemplate {
+    pub body: String,
+    /// Start of the assistant's answer (see --prefill).
+    pub prefill: Option<String>,
+}
+
+impl PromptTemplate {
+    /// A template for a literal prompt given on the command line; no front
+    /// matter is parsed.
+    pub fn literal(text: &str) -> Self {
+        Self {
+            body: text.to_string(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Splits a prompt file into its front matter options and body.
+pub fn parse_template(content: &str) -> PromptTemplate {
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return PromptTemplate::literal(content);
+    };
+
+    // Find the closing delimiter; without one the file has no front matter.
+    let mut offset = 0;
+    let mut front = Vec::new();
+    let mut body_start = None;
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        if line.trim_end() == "---" {
+            body_start = Some(offset);
+            break;
+        }
+        front.push(line.trim_end());
+    }
+    let Some(body_start) = body_start else {
+        return PromptTemplate::literal(content);
+    };
+
+    let mut template = PromptTemplate::literal(rest[body_start..].trim_start_matches(['\r', '\n']));
+    for line in front {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        let value = serde_json::from_str::<String>(value).unwrap_or_else(|_| value.to_string());
+        if key.trim() == "prefill" {
+            template.prefill = Some(value);
+        }
+    }
+    template
+}
+
 /// List available prompt names (filenames without .md extension), sorted.
 /// Returns an empty list if the prompts directory does not exist.
 pub fn list_prompts(prompts_dir: &Path) -> Result<Vec<String>, CrabError> {
@@ -1,35 +1,44 @@
 use async_trait::async_trait;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
+use super::http::HttpClient;
 use super::r#trait::Provider;
+use super::{batch_index, omit_params};
+use crate::api_key;
+use crate::config::Config;
 use crate::error::CrabError;
 
-use crate::types::ModelInfo;
+use crate::types::{
+    BatchResult, BatchStatus, Capabilities, ChatMessage, ChatRequest, Completion, FinishReason,
+    ModelInfo, Role, Source,
+};
 
 /// Anthropic Messages API. Uses a custom request format (not OpenAI-compatible).
 /// Model listing returns a static fallback list; no API key required for that.
 pub struct AnthropicProvider {
-    client: Client,
-    api_key: Option<String>,
+    client: HttpClient,
+    base_url: String,
+    api_keys: api_key::Keys,
 }
 
 impl AnthropicProvider {
-    const API_URL: &'static str = "https://api.anthropic.com/v1/messages";
+    const BASE_URL: &'static str = "https://api.anthropic.com/v1";
     const API_VERSION: &'static str = "2023-06-01";
+    /// Server-side web search tool version.
+    const WEB_SEARCH_TOOL: &'static str = "web_search_20250305";
 
-    /// Creates a new provider instance with a custom environment variable name.
-    pub fn new_with_env(env_var: &str) -> Result<Self, CrabError> {
-        let api_key = std::env::var(env_var).ok();
+    /// Creates a new provider instance from the application config.
+    pub fn new(config: &Config) -> Result<Self, CrabError> {
         Ok(Self {
-            client: Client::new(),
-            api_key,
+            client: HttpClient::new("anthropic", config)?,
+            base_url: config.provider_base_url("anthropic", Self::BASE_URL),
+            api_keys: config.api_key_sources("anthropic")?,
         })
     }
 
     fn require_key(&self) -> Result<&str, CrabError> {
-        self.api_key
-            .as_deref()
+        self.api_keys
+            .active()?
             .ok_or_else(|| CrabError::MissingApiKey("anthropic".to_string()))
     }
 
@@ -54,23 +63,18 @@ impl AnthropicProvider {
     /// Fetch models from Anthropic's models API endpoint.
     async fn list_models_api(&self) -> Result<Vec<ModelInfo>, CrabError> {
         let api_key = self.require_key()?;
-        let url = "https://api.anthropic.com/v1/models";
+        let url = format!("{}/models", self.base_url);
 
         let resp = self
             .client
-            .get(url)
-            .header("x-api-key", api_key)
-            .header("anthropic-version", Self::API_VERSION)
-            .send()
+            .execute(
+                self.client
+                    .get(&url)
+                    .header("x-api-key", api_key)
+                    .header("anthropic-version", Self::API_VERSION),
+            )
             .await?;
 
-        if !resp.status().is_success() {
-            return Err(CrabError::ProviderError {
-                provider: "anthropic".to_string(),
-                message: format!("HTTP {}", resp.status()),
-            });
-        }
-
         #[derive(Deserialize)]
         struct ModelsResponse {
             data: Vec<ModelEntry>,
@@ -92,6 +96,159 @@ impl AnthropicProvider {
 
         Ok(models)
     }
+
+    /// The original Claude 3 models predate PDF support and server tools.
+    fn is_original_claude_3(model: &str) -> bool {
+        ["claude-3-opus", "claude-3-sonnet", "claude-3-haiku"]
+            .iter()
+            .any(|prefix| model.starts_with(prefix))
+    }
+
+    /// The message text, or content blocks with the attachments first:
+    /// images as `image` blocks and PDFs as `document` blocks.
+    fn content(message: &ChatMessage) -> serde_json::Value {
+        if message.attachments.is_empty() {
+            return serde_json::json!(message.content);
+        }
+        let mut blocks: Vec<serde_json::Value> = message
+            .attachments
+            .iter()
+            .map(|a| {
+                serde_json::json!({
+                    "type": if a.is_pdf() { "document" } else { "image" },
+                    "source": {
+                        "type": "base64",
+                        "media_type": a.mime_type,
+                        "data": a.base64(),
+                    },
+                })
+            })
+            .collect();
+        if !message.content.is_empty() {
+            blocks.push(serde_json::json!({ "type": "text", "text": message.content }));
+        }
+        serde_json::Value::Array(blocks)
+    }
+
+    /// Builds the Messages API request body.
+    fn message_body(request: &ChatRequest) -> Result<serde_json::Value, CrabError> {
+        let body = AnthropicRequest {
+            model: request.model.clone(),
+            messages: request
+                .messages
+                .iter()
+                .map(|m| AnthropicMessage {
+                    role: m.role.as_str().to_string(),
+                    content: Self::content(m),
+                })
+                .chain(request.prefill.as_ref().map(|p| AnthropicMessage {
+                    role: Role::Assistant.as_str().to_string(),
+                    content: serde_json::json!(p),
+                }))
+                .collect(),
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            tools: if request.web_search {
+                vec![serde_json::json!({
+                    "type": Self::WEB_SEARCH_TOOL,
+                    "name": "web_search",
+                })]
+            } else {
+                Vec::new()
+            },
+        };
+        let mut body = serde_json::to_value(body)?;
+        omit_params(&mut body, &request.omit_params);
+        Ok(body)
+    }
+
+    /// Converts a Messages API response into a completion. None if the
+    /// response holds no text.
+    fn completion(resp: AnthropicResponse) -> Option<Completion> {
+        let mut text = String::new();
+        let mut citations = Vec::new();
+        for block in resp.content {
+            text.push_str(block.text.as_deref().unwrap_or_default());
+            citations.extend(block.citations.unwrap_or_default());
+        }
+        if text.is_empty() {
+            return None;
+        }
+        let mut completion = Completion {
+            text,
+            finish_reason: resp.stop_reason.as_deref().map(FinishReason::parse),
+            output_tokens: resp.usage.and_then(|u| u.output_tokens),
+            logprobs: None,
+            sources: Vec::new(),
+            id: None,
+            upstream_provider: None,
+        };
+        completion.add_sources(citations.into_iter().filter_map(|c| {
+            Some(Source {
+                url: c.url?,
+                title: c.title,
+            })
+        }));
+        Some(completion)
+    }
+
+    /// GET a Message Batches API URL with the authentication headers.
+    async fn get_batch_url(&self, url: &str) -> Result<reqwest::Response, CrabError> {
+        let api_key = self.require_key()?;
+        self.client
+            .execute(
+                self.client
+                    .get(url)
+                    .header("x-api-key", api_key)
+                    .header("anthropic-version", Self::API_VERSION),
+            )
+            .await
+    }
+
+    async fn get_batch(&self, id: &str) -> Result<MessageBatch, CrabError> {
+        let url = format!("{}/messages/batches/{id}", self.base_url);
+        Ok(self.get_batch_url(&url).await?.json().await?)
+    }
+}
+
+/// A Message Batches job.
+#[derive(Deserialize)]
+struct MessageBatch {
+    id: String,
+    processing_status: String,
+    request_counts: BatchCounts,
+    results_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BatchCounts {
+    processing: u32,
+    succeeded: u32,
+    errored: u32,
+    canceled: u32,
+    expired: u32,
+}
+
+impl MessageBatch {
+    fn status(self) -> BatchStatus {
+        let counts = self.request_counts;
+        let failed = counts.errored + counts.canceled + counts.expired;
+        BatchStatus {
+            id: self.id,
+            finished: self.processing_status == "ended",
+            status: self.processing_status,
+            total: counts.processing + counts.succeeded + failed,
+            succeeded: counts.succeeded,
+            failed,
+        }
+    }
+}
+
+/// One line of a batch results file.
+#[derive(Deserialize)]
+struct BatchResultLine {
+    custom_id: String,
+    result: serde_json::Value,
 }
 
 #[derive(Serialize)]
@@ -101,90 +258,174 @@ struct AnthropicRequest {
     max_tokens: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<serde_json::Value>,
 }
 
 #[derive(Serialize)]
 struct AnthropicMessage {
     role: String,
-    content: String,
+    /// Plain text, or content blocks when files are attached.
+    content: serde_json::Value,
 }
 
 #[derive(Deserialize)]
 struct AnthropicResponse {
     content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<AnthropicUsage>,
 }
 
 #[derive(Deserialize)]
 struct ContentBlock {
-    text: String,
+    /// Absent for non-text blocks such as extended thinking.
+    text: Option<String>,
+    /// Web pages a text block cites, when web search was used.
+    citations: Option<Vec<Citation>>,
+}
+
+#[derive(Deserialize)]
+struct Citation {
+    url: Option<String>,
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+    output_tokens: Option<u32>,
 }
 
 #[async_trait]
 impl Provider for AnthropicProvider {
-    async fn send(
-        &self,
-        model: &str,
-        prompt: &str,
-        temperature: Option<f32>,
-        max_tokens: u32,
-        _max_tokens_key: Option<String>,
-    ) -> Result<String, CrabError> {
-        let request = AnthropicRequest {
-            model: model.to_string(),
-            messages: vec![AnthropicMessage {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-            }],
-            max_tokens,
-            temperature,
-        };
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
+        let body = Self::message_body(request)?;
+        let api_key = self.require_key()?;
+
+        let resp = self
+            .client
+            .execute(
+                self.client
+                    .post(&format!("{}/messages", self.base_url))
+                    .header("x-api-key", api_key)
+                    .header("anthropic-version", Self::API_VERSION)
+                    .header("content-type", "application/json")
+                    .json(&body),
+            )
+            .await?;
 
+        let anthropic_resp: AnthropicResponse = resp.json().await?;
+        Self::completion(anthropic_resp).ok_or_else(|| CrabError::ProviderError {
+            provider: "anthropic".to_string(),
+            message: "Empty response".to_string(),
+        })
+    }
+
+    async fn submit_batch(&self, requests: &[ChatRequest]) -> Result<BatchStatus, CrabError> {
+        let requests = requests
+            .iter()
+            .enumerate()
+            .map(|(index, request)| {
+                Ok(serde_json::json!({
+                    "custom_id": index.to_string(),
+                    "params": Self::message_body(request)?,
+                }))
+            })
+            .collect::<Result<Vec<_>, CrabError>>()?;
         let api_key = self.require_key()?;
 
         let resp = self
             .client
-            .post(Self::API_URL)
-            .header("x-api-key", api_key)
-            .header("anthropic-version", Self::API_VERSION)
-            .header("content-type", "application/json")
-            .json(&request)
-            .send()
+            .execute(
+                self.client
+                    .post(&format!("{}/messages/batches", self.base_url))
+                    .header("x-api-key", api_key)
+                    .header("anthropic-version", Self::API_VERSION)
+                    .json(&serde_json::json!({ "requests": requests })),
+            )
             .await?;
+        let batch: MessageBatch = resp.json().await?;
+        Ok(batch.status())
+    }
+
+    async fn batch_status(&self, id: &str) -> Result<BatchStatus, CrabError> {
+        Ok(self.get_batch(id).await?.status())
+    }
+
+    async fn fetch_batch(&self, id: &str) -> Result<Vec<BatchResult>, CrabError> {
+        let batch = self.get_batch(id).await?;
+        let url = batch.results_url.ok_or_else(|| CrabError::ProviderError {
+            provider: "anthropic".to_string(),
+            message: format!("Batch {id} has no results yet"),
+        })?;
+        let content = self.get_batch_url(&url).await?.text().await?;
 
-        let status = resp.status();
-        if !status.is_success() {
-            let body = resp.text().await.unwrap_or_default();
-            return Err(CrabError::ProviderError {
-                provider: "anthropic".to_string(),
-                message: format!("HTTP {status}: {body}"),
+        let mut results = Vec::new();
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            let entry: BatchResultLine = serde_json::from_str(line)?;
+            let result = entry.result;
+            let outcome = match result["type"].as_str() {
+                Some("succeeded") => serde_json::from_value(result["message"].clone())
+                    .map_err(|e| e.to_string())
+                    .and_then(|resp| {
+                        Self::completion(resp).ok_or_else(|| "Empty response".to_string())
+                    }),
+                Some("errored") => Err(result["error"]["error"]["message"]
+                    .as_str()
+                    .unwrap_or("Request failed")
+                    .to_string()),
+                other => Err(format!("Request {}", other.unwrap_or("failed"))),
+            };
+            results.push(BatchResult {
+                index: batch_index("anthropic", &entry.custom_id)?,
+                outcome,
             });
         }
-
-        let anthropic_resp: AnthropicResponse = resp.json().await?;
-        anthropic_resp
-            .content
-            .into_iter()
-            .next()
-            .map(|b| b.text)
-            .ok_or_else(|| CrabError::ProviderError {
-                provider: "anthropic".to_string(),
-                message: "Empty response".to_string(),
-            })
+        Ok(results)
     }
 
     async fn list_models(&self) -> Result<Vec<ModelInfo>, CrabError> {
-        // Try to fetch from API first, fall back to static list on failure
-        match self.list_models_api().await {
-            Ok(models) => Ok(models),
-            Err(_) => Ok(Self::static_models()),
+        // Without an API key, fall back to the static list.
+        if self.api_keys.active()?.is_none() {
+            return Ok(Self::static_models());
         }
+        self.list_models_api().await
     }
 
     fn name(&self) -> &str {
         "anthropic"
     }
 
-    fn sanitize_params(&self, _model: &str, temperature: f32, max_tokens: u32) -> (Option<f32>, u32) {
-        (Some(temperature), max_tokens)
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            system_role: true,
+            streaming: true,
+            tools: true,
+            vision: true,
+            pdf: true,
+            json_schema: false,
+            reasoning: true,
+            prefill: true,
+            logprobs: false,
+            web_search: true,
+            temperature: Some((0.0, 1.0)),
+            max_tokens_param: None,
+        }
+    }
+
+    fn model_capabilities(&self, model: &str) -> Capabilities {
+        // Every model since Claude 3 reads images and calls tools.
+        let claude_3 = !model.starts_with("claude-2") && !model.starts_with("claude-instant");
+        Capabilities {
+            tools: claude_3,
+            vision: claude_3,
+            pdf: claude_3 && !Self::is_original_claude_3(model),
+            // Extended thinking arrived with Claude 3.7 Sonnet.
+            reasoning: model.starts_with("claude-3-7")
+                || ["claude-sonnet-4", "claude-opus-4", "claude-haiku-4"]
+                    .iter()
+                    .any(|prefix| model.starts_with(prefix)),
+            web_search: !Self::is_original_claude_3(model),
+            ..self.capabilities()
+        }
     }
 }
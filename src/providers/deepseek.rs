@@ -1,33 +1,42 @@
 use async_trait::async_trait;
-use reqwest::Client;
 
 use super::openai_compat;
+use super::http::HttpClient;
 use super::r#trait::Provider;
+use crate::api_key;
+use crate::config::Config;
 use crate::error::CrabError;
-
-use crate::types::ModelInfo;
+use crate::types::{Capabilities, ChatRequest, Completion, FimRequest, ModelInfo};
 
 /// DeepSeek chat API (OpenAI-compatible). Model listing falls back to a
 /// static list if no API key is set or if the models endpoint fails.
 pub struct DeepSeekProvider {
-    client: Client,
-    api_key: Option<String>,
+    client: HttpClient,
+    base_url: String,
+    api_keys: api_key::Keys,
 }
 
 impl DeepSeekProvider {
     const BASE_URL: &'static str = "https://api.deepseek.com";
 
-    /// Creates a new provider instance with a custom environment variable name.
-    pub fn new_with_env(env_var: &str) -> Result<Self, CrabError> {
+    /// Creates a new provider instance from the application config.
+    pub fn new(config: &Config) -> Result<Self, CrabError> {
         Ok(Self {
-            client: Client::new(),
-            api_key: std::env::var(env_var).ok(),
+            client: HttpClient::new("deepseek", config)?,
+            base_url: config.provider_base_url("deepseek", Self::BASE_URL),
+            api_keys: config.api_key_sources("deepseek")?,
         })
     }
 
+    /// Chat prefix completion (prefill) and FIM completion are only served
+    /// by the beta endpoint.
+    fn beta_url(&self) -> String {
+        format!("{}/beta", self.base_url)
+    }
+
     fn require_key(&self) -> Result<&str, CrabError> {
-        self.api_key
-            .as_deref()
+        self.api_keys
+            .active()?
             .ok_or_else(|| CrabError::MissingApiKey("deepseek".to_string()))
     }
 
@@ -47,25 +56,20 @@ impl DeepSeekProvider {
 
 #[async_trait]
 impl Provider for DeepSeekProvider {
-    async fn send(
-        &self,
-        model: &str,
-        prompt: &str,
-        temperature: Option<f32>,
-        max_tokens: u32,
-        max_tokens_key: Option<String>,
-    ) -> Result<String, CrabError> {
-        openai_compat::send_chat_request(
-            &self.client,
-            Self::BASE_URL,
-            self.require_key()?,
-            model,
-            prompt,
-            temperature,
-            max_tokens,
-            max_tokens_key,
-        )
-        .await
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
+        let api_key = self.require_key()?;
+        if request.prefill.is_none() {
+            return openai_compat::send_chat_request(&self.client, &self.base_url, api_key, request)
+                .await;
+        }
+        let mut body = openai_compat::chat_body(request);
+        openai_compat::mark_prefix(&mut body);
+        openai_compat::post_chat(&self.client, &self.beta_url(), api_key, &body).await
+    }
+
+    async fn complete_fim(&self, request: &FimRequest) -> Result<Completion, CrabError> {
+        let url = format!("{}/completions", self.beta_url());
+        openai_compat::post_fim(&self.client, &url, self.require_key()?, request).await
     }
 
     async fn list_models(&self) -> Result<Vec<ModelInfo>, CrabError> {
@@ -73,29 +77,47 @@ impl Provider for DeepSeekProvider {
             Ok(k) => k,
             Err(_) => return Ok(Self::static_models()),
         };
-        match openai_compat::list_models_api(&self.client, Self::BASE_URL, api_key).await {
-            Ok(mut models) => {
-                for m in &mut models {
-                    if m.id == "deepseek-reasoner" {
-                        m.supports_temperature = false;
-                    }
-                }
-                Ok(models)
+        let mut models =
+            openai_compat::list_models_api(&self.client, &self.base_url, api_key).await?;
+        for m in &mut models {
+            if m.id == "deepseek-reasoner" {
+                m.supports_temperature = false;
             }
-            Err(_) => Ok(Self::static_models()),
         }
+        Ok(models)
     }
 
     fn name(&self) -> &str {
         "deepseek"
     }
 
-    fn sanitize_params(&self, model: &str, temperature: f32, max_tokens: u32) -> (Option<f32>, u32) {
-        if model == "deepseek-reasoner" {
-            // Omit temperature for deepseek-reasoner.
-            (None, max_tokens)
-        } else {
-            (Some(temperature), max_tokens)
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            system_role: true,
+            streaming: true,
+            tools: true,
+            reasoning: true,
+            prefill: true,
+            logprobs: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn model_capabilities(&self, model: &str) -> Capabilities {
+        if model != "deepseek-reasoner" {
+            return Capabilities {
+                reasoning: false,
+                ..self.capabilities()
+            };
+        }
+        // The reasoner supports neither prefix completion, logprobs, tools
+        // nor a temperature.
+        Capabilities {
+            tools: false,
+            prefill: false,
+            logprobs: false,
+            temperature: None,
+            ..self.capabilities()
         }
     }
 }
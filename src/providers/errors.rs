@@ -0,0 +1,206 @@
+//! Converts failed provider HTTP responses into typed `CrabError` variants.
+//!
+//! Error bodies come in a few shapes:
+//! - OpenAI, Groq, DeepSeek, OpenRouter, Together:
+//!   `{"error": {"message", "type", "code", "param"}}`
+//! - Anthropic: `{"type": "error", "error": {"type", "message"}}`
+//! - Google: `{"error": {"code", "message", "status"}}`
+//! - Mistral: `{"object": "error", "message", "type", "code"}` (top level)
+//!
+//! Classification looks at the provider's error type/code first, then at
+//! well-known message phrases, and finally falls back to the HTTP status.
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::error::{ApiError, CrabError};
+
+/// Response headers that carry a provider request id, in lookup order.
+const REQUEST_ID_HEADERS: &[&str] = &["x-request-id", "request-id", "x-goog-request-id"];
+
+/// Builds the typed error for a non-success provider response.
+pub fn from_response(
+    provider: &str,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &str,
+) -> CrabError {
+    let parsed: Option<Value> = serde_json::from_str(body).ok();
+    let details = parsed.as_ref().map(error_object);
+
+    let message = details
+        .and_then(|d| {
+            d.get("message")
+                .or_else(|| d.get("detail"))
+                .or_else(|| d.get("error"))
+        })
+        .map(value_text)
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| {
+            if body.trim().is_empty() {
+                status
+                    .canonical_reason()
+                    .unwrap_or("request failed")
+                    .to_string()
+            } else {
+                body.trim().to_string()
+            }
+        });
+
+    // Error type and code identifiers, e.g. "rate_limit_error", "RESOURCE_EXHAUSTED".
+    let codes: Vec<String> = details
+        .map(|d| {
+            ["type", "code", "status"]
+                .iter()
+                .filter_map(|k| d.get(*k))
+                .map(|v| value_text(v).to_lowercase())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let request_id = REQUEST_ID_HEADERS
+        .iter()
+        .find_map(|h| headers.get(*h))
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| {
+            parsed
+                .as_ref()
+                .and_then(|p| p.get("request_id"))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        });
+
+    let retry_after = headers
+        .get("retry-after")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .map(|secs| secs.ceil() as u64);
+
+    let kind = classify(status, &codes, &message);
+    let error = ApiError {
+        provider: provider.to_string(),
+        status: status.as_u16(),
+        message,
+        request_id,
+        retry_after,
+    };
+
+    match kind {
+        Some(Kind::Auth) => CrabError::Auth(error),
+        Some(Kind::RateLimited) => CrabError::RateLimited(error),
+        Some(Kind::ContextLength) => CrabError::ContextLength(error),
+        Some(Kind::InvalidParameter) => CrabError::InvalidParameter(error),
+        Some(Kind::ContentFiltered) => CrabError::ContentFiltered(error),
+        Some(Kind::ServerError) => CrabError::ServerError(error),
+        Some(Kind::ModelNotFound) => CrabError::ModelNotFound(error),
+        None => CrabError::ProviderError {
+            message: match &error.request_id {
+                Some(id) => format!(
+                    "HTTP {}: {} (request id: {id})",
+                    error.status, error.message
+                ),
+                None => format!("HTTP {}: {}", error.status, error.message),
+            },
+            provider: error.provider,
+        },
+    }
+}
+
+enum Kind {
+    Auth,
+    RateLimited,
+    ContextLength,
+    InvalidParameter,
+    ContentFiltered,
+    ServerError,
+    ModelNotFound,
+}
+
+fn classify(status: StatusCode, codes: &[String], message: &str) -> Option<Kind> {
+    let has_code = |needles: &[&str]| codes.iter().any(|c| needles.iter().any(|n| c.contains(n)));
+    let lower = message.to_lowercase();
+    let has_phrase = |needles: &[&str]| needles.iter().any(|n| lower.contains(n));
+
+    if has_code(&["context_length", "string_above_max_length"])
+        || has_phrase(&[
+            "maximum context length",
+            "context window",
+            "context length",
+            "prompt is too long",
+            "input is too long",
+        ])
+    {
+        return Some(Kind::ContextLength);
+    }
+    if has_code(&["content_filter", "content_policy", "moderation", "safety"])
+        || has_phrase(&["content management policy", "flagged by", "content filter"])
+    {
+        return Some(Kind::ContentFiltered);
+    }
+    if has_code(&["model_not_found"])
+        || (status == StatusCode::NOT_FOUND && has_phrase(&["model"]))
+        || has_phrase(&[
+            "model not found",
+            "does not exist or you do not have access",
+        ])
+    {
+        return Some(Kind::ModelNotFound);
+    }
+    if has_code(&[
+        "rate_limit",
+        "insufficient_quota",
+        "resource_exhausted",
+        "quota",
+    ]) {
+        return Some(Kind::RateLimited);
+    }
+    if has_code(&[
+        "authentication",
+        "permission",
+        "invalid_api_key",
+        "unauthenticated",
+    ]) || has_phrase(&["api key not valid", "invalid api key", "incorrect api key"])
+    {
+        return Some(Kind::Auth);
+    }
+    if has_code(&[
+        "overloaded",
+        "server_error",
+        "internal",
+        "unavailable",
+        "api_error",
+    ]) {
+        return Some(Kind::ServerError);
+    }
+
+    match status.as_u16() {
+        401 | 403 => Some(Kind::Auth),
+        429 => Some(Kind::RateLimited),
+        404 => Some(Kind::ModelNotFound),
+        413 => Some(Kind::ContextLength),
+        400 | 422 => Some(Kind::InvalidParameter),
+        s if s >= 500 => Some(Kind::ServerError),
+        _ => None,
+    }
+}
+
+/// Returns the object holding the error fields: the nested `error` object
+/// when present, otherwise the body itself (Mistral, FastAPI-style `detail`).
+fn error_object(body: &Value) -> &Value {
+    match body.get("error") {
+        Some(inner) if inner.is_object() => inner,
+        _ => body,
+    }
+}
+
+/// Renders a JSON value as plain text: strings verbatim, numbers and other
+/// values (such as validation `detail` arrays) as compact JSON.
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
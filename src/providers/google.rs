@@ -1,31 +1,84 @@
 use async_trait::async_trait;
-use reqwest::Client;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 
+use super::http::HttpClient;
+use super::omit_params;
 use super::r#trait::Provider;
-use crate::error::CrabError;
-use crate::types::ModelInfo;
+use crate::api_key;
+use crate::config::Config;
+use crate::error::{ApiError, CrabError};
+use crate::types::{
+    Capabilities, ChatMessage, ChatRequest, Completion, DEFAULT_TEMPERATURE_RANGE, EmbedRequest,
+    FinishReason, GeneratedImage, ImageOptions, ImageRequest, ModelInfo, Role, Source, TokenLogprob,
+    TopLogprob, TranscriptionRequest,
+};
 
 /// Google Gemini API. Uses a custom request format (not OpenAI-compatible).
 /// Authentication is via query parameter, not Authorization header.
 pub struct GoogleProvider {
-    client: Client,
-    api_key: Option<String>,
+    client: HttpClient,
+    base_url: String,
+    api_keys: api_key::Keys,
+    /// Blocking thresholds from `advanced.google.safety_settings`.
+    safety_settings: Vec<SafetySetting>,
 }
 
 impl GoogleProvider {
     const BASE_URL: &'static str = "https://generativelanguage.googleapis.com/v1beta";
+    /// Model used for audio input when none is given.
+    const TRANSCRIPTION_MODEL: &'static str = "gemini-2.0-flash";
+    const TRANSCRIPTION_PROMPT: &'static str =
+        "Generate a verbatim transcript of the speech in this audio. Output only the transcript.";
+
+    /// Safety thresholds accepted in `advanced.google.safety_settings`.
+    const SAFETY_THRESHOLDS: &'static [&'static str] = &[
+        "BLOCK_NONE",
+        "BLOCK_ONLY_HIGH",
+        "BLOCK_MEDIUM_AND_ABOVE",
+        "BLOCK_LOW_AND_ABOVE",
+        "OFF",
+    ];
+
+    /// Creates a new provider instance from the application config.
+    pub fn new(config: &Config) -> Result<Self, CrabError> {
+        let configured = config
+            .advanced
+            .as_ref()
+            .and_then(|a| a.google.as_ref())
+            .and_then(|g| g.safety_settings.clone())
+            .unwrap_or_default();
+        let mut safety_settings = Vec::new();
+        for (category, threshold) in configured {
+            let threshold = threshold.to_uppercase();
+            if !Self::SAFETY_THRESHOLDS.contains(&threshold.as_str()) {
+                return Err(CrabError::ConfigError(format!(
+                    "Invalid safety threshold for {category}: {threshold} (expected one of {})",
+                    Self::SAFETY_THRESHOLDS.join(", ")
+                )));
+            }
+            let category = category.to_uppercase().replace('-', "_");
+            safety_settings.push(SafetySetting {
+                category: if category.starts_with("HARM_CATEGORY_") {
+                    category
+                } else {
+                    format!("HARM_CATEGORY_{category}")
+                },
+                threshold,
+            });
+        }
 
-    pub fn new_with_env(env_var: &str) -> Result<Self, CrabError> {
         Ok(Self {
-            client: Client::new(),
-            api_key: std::env::var(env_var).ok(),
+            client: HttpClient::new("google", config)?,
+            base_url: config.provider_base_url("google", Self::BASE_URL),
+            api_keys: config.api_key_sources("google")?,
+            safety_settings,
         })
     }
 
     fn require_key(&self) -> Result<&str, CrabError> {
-        self.api_key
-            .as_deref()
+        self.api_keys
+            .active()?
             .ok_or_else(|| CrabError::MissingApiKey("google".to_string()))
     }
 
@@ -46,6 +99,16 @@ struct GeminiRequest {
     contents: Vec<GeminiContent>,
     #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
     generation_config: Option<GenerationConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<serde_json::Value>,
+    #[serde(rename = "safetySettings", skip_serializing_if = "Vec::is_empty")]
+    safety_settings: Vec<SafetySetting>,
+}
+
+#[derive(Serialize, Clone)]
+struct SafetySetting {
+    category: String,
+    threshold: String,
 }
 
 #[derive(Serialize)]
@@ -54,90 +117,534 @@ struct GeminiContent {
     parts: Vec<GeminiPart>,
 }
 
+/// A text part, or a file sent inline (image or PDF).
 #[derive(Serialize)]
 struct GeminiPart {
-    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inline_data: Option<GeminiBlob>,
+}
+
+#[derive(Serialize)]
+struct GeminiBlob {
+    mime_type: String,
+    /// Base64-encoded file contents.
+    data: String,
 }
 
 #[derive(Serialize)]
 struct GenerationConfig {
-    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
     #[serde(rename = "maxOutputTokens")]
     max_output_tokens: u32,
+    #[serde(rename = "responseLogprobs", skip_serializing_if = "Option::is_none")]
+    response_logprobs: Option<bool>,
+    /// Number of top candidate tokens returned per position.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<u8>,
+    /// Number of independent answers to generate.
+    #[serde(rename = "candidateCount", skip_serializing_if = "Option::is_none")]
+    candidate_count: Option<u32>,
 }
 
 #[derive(Deserialize)]
 struct GeminiResponse {
     candidates: Option<Vec<Candidate>>,
+    #[serde(rename = "promptFeedback")]
+    prompt_feedback: Option<PromptFeedback>,
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+/// Set when the prompt itself was blocked; there are no candidates then.
+#[derive(Deserialize)]
+struct PromptFeedback {
+    #[serde(rename = "blockReason")]
+    block_reason: Option<String>,
+    #[serde(rename = "blockReasonMessage")]
+    block_reason_message: Option<String>,
+    #[serde(rename = "safetyRatings", default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Deserialize)]
+struct SafetyRating {
+    category: String,
+    #[serde(default)]
+    probability: String,
+    /// True for the categories that caused the block.
+    #[serde(default)]
+    blocked: bool,
 }
 
 #[derive(Deserialize)]
 struct Candidate {
-    content: CandidateContent,
+    content: Option<CandidateContent>,
+    #[serde(rename = "finishReason")]
+    finish_reason: Option<String>,
+    #[serde(rename = "logprobsResult")]
+    logprobs_result: Option<LogprobsResult>,
+    #[serde(rename = "groundingMetadata")]
+    grounding_metadata: Option<GroundingMetadata>,
+    #[serde(rename = "safetyRatings", default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Deserialize)]
+struct GroundingMetadata {
+    #[serde(rename = "groundingChunks", default)]
+    grounding_chunks: Vec<GroundingChunk>,
+}
+
+#[derive(Deserialize)]
+struct GroundingChunk {
+    web: Option<WebChunk>,
+}
+
+#[derive(Deserialize)]
+struct WebChunk {
+    uri: Option<String>,
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LogprobsResult {
+    #[serde(rename = "chosenCandidates", default)]
+    chosen_candidates: Vec<LogprobCandidate>,
+    #[serde(rename = "topCandidates", default)]
+    top_candidates: Vec<TopCandidates>,
+}
+
+#[derive(Deserialize)]
+struct TopCandidates {
+    #[serde(default)]
+    candidates: Vec<LogprobCandidate>,
+}
+
+#[derive(Deserialize)]
+struct LogprobCandidate {
+    #[serde(default)]
+    token: String,
+    #[serde(rename = "logProbability", default)]
+    log_probability: f64,
 }
 
 #[derive(Deserialize)]
 struct CandidateContent {
+    #[serde(default)]
     parts: Vec<CandidatePart>,
 }
 
 #[derive(Deserialize)]
 struct CandidatePart {
-    text: String,
+    text: Option<String>,
+    #[serde(rename = "inlineData")]
+    inline_data: Option<InlineData>,
 }
 
-#[async_trait]
-impl Provider for GoogleProvider {
-    async fn send(
+#[derive(Deserialize)]
+struct InlineData {
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct PredictResponse {
+    #[serde(default)]
+    predictions: Vec<Prediction>,
+}
+
+#[derive(Deserialize)]
+struct Prediction {
+    #[serde(rename = "bytesBase64Encoded")]
+    bytes_base64_encoded: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BatchEmbedResponse {
+    #[serde(default)]
+    embeddings: Vec<EmbeddingValues>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingValues {
+    values: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct UsageMetadata {
+    #[serde(rename = "candidatesTokenCount")]
+    candidates_token_count: Option<u32>,
+}
+
+impl GoogleProvider {
+    /// Message parts: the attachments as inline data, then the text.
+    fn parts(message: &ChatMessage) -> Vec<GeminiPart> {
+        let mut parts: Vec<GeminiPart> = message
+            .attachments
+            .iter()
+            .map(|a| GeminiPart {
+                text: None,
+                inline_data: Some(GeminiBlob {
+                    mime_type: a.mime_type.clone(),
+                    data: a.base64(),
+                }),
+            })
+            .collect();
+        if !message.content.is_empty() || parts.is_empty() {
+            parts.push(GeminiPart {
+                text: Some(message.content.clone()),
+                inline_data: None,
+            });
+        }
+        parts
+    }
+
+    /// Adds the configured safety thresholds to a generateContent body
+    /// built as JSON.
+    fn add_safety_settings(&self, body: &mut serde_json::Value) -> Result<(), CrabError> {
+        if !self.safety_settings.is_empty() {
+            body["safetySettings"] = serde_json::to_value(&self.safety_settings)?;
+        }
+        Ok(())
+    }
+
+    /// Grounding tool for the model: Gemini 1.5 uses Google Search retrieval,
+    /// later models the google_search tool.
+    fn search_tool(model: &str) -> serde_json::Value {
+        if model.starts_with("gemini-1.5") {
+            serde_json::json!({ "google_search_retrieval": {} })
+        } else {
+            serde_json::json!({ "google_search": {} })
+        }
+    }
+
+    /// Calls generateContent, asking for `candidate_count` answers when set.
+    async fn generate(
         &self,
-        model: &str,
-        prompt: &str,
-        temperature: Option<f32>,
-        max_tokens: u32,
-        _max_tokens_key: Option<String>,
-    ) -> Result<String, CrabError> {
+        request: &ChatRequest,
+        candidate_count: Option<u32>,
+    ) -> Result<Vec<Completion>, CrabError> {
         let api_key = self.require_key()?;
         let url = format!(
             "{}/models/{}:generateContent?key={}",
-            Self::BASE_URL,
-            model,
+            &self.base_url,
+            request.model,
             api_key
         );
 
-        let request = GeminiRequest {
-            contents: vec![GeminiContent {
-                role: "user".to_string(),
-                parts: vec![GeminiPart {
-                    text: prompt.to_string(),
-                }],
-            }],
+        let body = GeminiRequest {
+            contents: request
+                .messages
+                .iter()
+                .map(|m| GeminiContent {
+                    // Gemini calls the assistant role "model".
+                    role: match m.role {
+                        Role::User => "user".to_string(),
+                        Role::Assistant => "model".to_string(),
+                    },
+                    parts: Self::parts(m),
+                })
+                .collect(),
             generation_config: Some(GenerationConfig {
-                temperature: temperature.unwrap_or(0.2), // Default if None
-                max_output_tokens: max_tokens,
+                temperature: request.temperature,
+                max_output_tokens: request.max_tokens,
+                response_logprobs: request.logprobs.map(|_| true),
+                logprobs: request.logprobs.filter(|k| *k > 0),
+                candidate_count,
             }),
+            tools: if request.web_search {
+                vec![Self::search_tool(&request.model)]
+            } else {
+                Vec::new()
+            },
+            safety_settings: self.safety_settings.clone(),
         };
+        let mut body = serde_json::to_value(body)?;
+        // Gemini nests sampling parameters under generationConfig.
+        omit_params(&mut body["generationConfig"], &request.omit_params);
+
+        let resp = self
+            .client
+            .execute(self.client.post(&url).json(&body))
+            .await?;
+
+        let gemini_resp: GeminiResponse = resp.json().await?;
+        // The reported token count covers all candidates.
+        let candidates = gemini_resp.candidates.unwrap_or_default();
+        let blocked = blocked_error(gemini_resp.prompt_feedback.as_ref(), &candidates);
+        let output_tokens = gemini_resp
+            .usage_metadata
+            .and_then(|u| u.candidates_token_count)
+            .filter(|_| candidates.len() == 1);
+        let completions: Vec<Completion> = candidates
+            .into_iter()
+            .map(|c| {
+                let mut completion = Completion {
+                    text: c
+                        .content
+                        .map(|content| content.parts.into_iter().filter_map(|p| p.text).collect())
+                        .unwrap_or_default(),
+                    finish_reason: c.finish_reason.as_deref().map(FinishReason::parse),
+                    output_tokens,
+                    logprobs: c.logprobs_result.map(token_logprobs),
+                    sources: Vec::new(),
+                    id: None,
+                    upstream_provider: None,
+                };
+                let chunks = c.grounding_metadata.map(|g| g.grounding_chunks);
+                completion.add_sources(
+                    chunks
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|chunk| chunk.web)
+                        .filter_map(|web| {
+                            Some(Source {
+                                url: web.uri?,
+                                title: web.title,
+                            })
+                        }),
+                );
+                completion
+            })
+            .filter(|c| !c.text.is_empty())
+            .collect();
+        if completions.is_empty() {
+            return Err(blocked.unwrap_or_else(|| CrabError::ProviderError {
+                provider: "google".to_string(),
+                message: "Empty response".to_string(),
+            }));
+        }
+        Ok(completions)
+    }
+}
+
+/// Why Gemini returned no answer: the prompt's block reason, or the finish
+/// reason of a candidate stopped by a safety filter. Names the harm
+/// categories responsible. None if nothing was blocked.
+fn blocked_error(feedback: Option<&PromptFeedback>, candidates: &[Candidate]) -> Option<CrabError> {
+    let prompt_blocked = feedback.filter(|f| f.block_reason.is_some());
+    let (what, reason, ratings, detail) = match prompt_blocked {
+        Some(feedback) => (
+            "Prompt",
+            feedback.block_reason.as_deref()?,
+            &feedback.safety_ratings,
+            feedback.block_reason_message.as_deref(),
+        ),
+        None => {
+            let candidate = candidates.iter().find(|c| {
+                c.finish_reason.as_deref().map(FinishReason::parse)
+                    == Some(FinishReason::ContentFilter)
+            })?;
+            (
+                "Answer",
+                candidate.finish_reason.as_deref()?,
+                &candidate.safety_ratings,
+                None,
+            )
+        }
+    };
 
-        let resp = self.client.post(&url).json(&request).send().await?;
+    let mut message = format!("{what} blocked ({reason})");
+    // Ratings flagged as blocking; otherwise the high and medium ones.
+    let mut culprits: Vec<&SafetyRating> = ratings.iter().filter(|r| r.blocked).collect();
+    if culprits.is_empty() {
+        culprits = ratings
+            .iter()
+            .filter(|r| r.probability == "HIGH" || r.probability == "MEDIUM")
+            .collect();
+    }
+    if !culprits.is_empty() {
+        let categories: Vec<String> = culprits
+            .iter()
+            .map(|r| format!("{} ({})", r.category, r.probability))
+            .collect();
+        message.push_str(&format!(": {}", categories.join(", ")));
+    }
+    if let Some(detail) = detail {
+        message.push_str(&format!(". {detail}"));
+    }
 
-        let status = resp.status();
-        if !status.is_success() {
-            let body = resp.text().await.unwrap_or_default();
+    Some(CrabError::ContentFiltered(ApiError {
+        provider: "google".to_string(),
+        status: 200,
+        message,
+        request_id: None,
+        retry_after: None,
+    }))
+}
+
+#[async_trait]
+impl Provider for GoogleProvider {
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
+        let mut completions = self.generate(request, None).await?;
+        Ok(completions.remove(0))
+    }
+
+    async fn send_n(&self, request: &ChatRequest, n: u32) -> Result<Vec<Completion>, CrabError> {
+        self.generate(request, Some(n)).await
+    }
+
+    async fn embed(&self, request: &EmbedRequest) -> Result<Vec<Vec<f32>>, CrabError> {
+        let api_key = self.require_key()?;
+        let url = format!(
+            "{}/models/{}:batchEmbedContents?key={}",
+            &self.base_url,
+            request.model,
+            api_key
+        );
+        let requests: Vec<serde_json::Value> = request
+            .inputs
+            .iter()
+            .map(|text| {
+                let mut entry = serde_json::json!({
+                    "model": format!("models/{}", request.model),
+                    "content": { "parts": [{ "text": text }] },
+                });
+                if let Some(dimensions) = request.dimensions {
+                    entry["outputDimensionality"] = serde_json::json!(dimensions);
+                }
+                entry
+            })
+            .collect();
+
+        let resp = self
+            .client
+            .execute(
+                self.client
+                    .post(&url)
+                    .json(&serde_json::json!({ "requests": requests })),
+            )
+            .await?;
+
+        let batch: BatchEmbedResponse = resp.json().await?;
+        if batch.embeddings.len() != request.inputs.len() {
             return Err(CrabError::ProviderError {
                 provider: "google".to_string(),
-                message: format!("HTTP {status}: {body}"),
+                message: format!(
+                    "Expected {} embeddings, got {}",
+                    request.inputs.len(),
+                    batch.embeddings.len()
+                ),
             });
         }
+        Ok(batch.embeddings.into_iter().map(|e| e.values).collect())
+    }
+
+    async fn transcribe(&self, request: &TranscriptionRequest) -> Result<String, CrabError> {
+        // Gemini takes audio as inline data next to a text instruction.
+        let api_key = self.require_key()?;
+        let model = request.model.as_deref().unwrap_or(Self::TRANSCRIPTION_MODEL);
+        let url = format!(
+            "{}/models/{}:generateContent?key={}",
+            &self.base_url,
+            model,
+            api_key
+        );
+        let mut body = serde_json::json!({
+            "contents": [{
+                "role": "user",
+                "parts": [
+                    {
+                        "inline_data": {
+                            "mime_type": request.mime_type,
+                            "data": base64::engine::general_purpose::STANDARD.encode(&request.data),
+                        }
+                    },
+                    { "text": Self::TRANSCRIPTION_PROMPT },
+                ],
+            }],
+        });
+        self.add_safety_settings(&mut body)?;
+
+        let resp = self
+            .client
+            .execute(self.client.post(&url).json(&body))
+            .await?;
 
         let gemini_resp: GeminiResponse = resp.json().await?;
-        gemini_resp
-            .candidates
-            .and_then(|c| c.into_iter().next())
-            .and_then(|c| c.content.parts.into_iter().next())
-            .map(|p| p.text)
-            .ok_or_else(|| CrabError::ProviderError {
+        let candidates = gemini_resp.candidates.unwrap_or_default();
+        if let Some(blocked) = blocked_error(gemini_resp.prompt_feedback.as_ref(), &candidates) {
+            return Err(blocked);
+        }
+        let text: String = candidates
+            .into_iter()
+            .next()
+            .and_then(|c| c.content)
+            .map(|content| content.parts.into_iter().filter_map(|p| p.text).collect())
+            .unwrap_or_default();
+        if text.is_empty() {
+            return Err(CrabError::ProviderError {
                 provider: "google".to_string(),
-                message: "Empty response".to_string(),
-            })
+                message: "Empty transcript".to_string(),
+            });
+        }
+        Ok(text)
+    }
+
+    async fn generate_images(
+        &self,
+        request: &ImageRequest,
+    ) -> Result<Vec<GeneratedImage>, CrabError> {
+        let api_key = self.require_key()?;
+        if request.model.starts_with("imagen") {
+            // Imagen models use the predict endpoint.
+            let url = format!(
+                "{}/models/{}:predict?key={}",
+                &self.base_url,
+                request.model,
+                api_key
+            );
+            let mut parameters = serde_json::json!({ "sampleCount": request.count });
+            if let Some(ratio) = &request.size {
+                parameters["aspectRatio"] = serde_json::json!(ratio);
+            }
+            let body = serde_json::json!({
+                "instances": [{ "prompt": request.prompt }],
+                "parameters": parameters,
+            });
+            let resp = self
+                .client
+                .execute(self.client.post(&url).json(&body))
+                .await?;
+            let predict: PredictResponse = resp.json().await?;
+            return predict
+                .predictions
+                .iter()
+                .filter_map(|p| p.bytes_base64_encoded.as_deref())
+                .map(decode_image)
+                .collect();
+        }
+
+        // Gemini image models answer through generateContent with inline image parts.
+        let url = format!(
+            "{}/models/{}:generateContent?key={}",
+            &self.base_url,
+            request.model,
+            api_key
+        );
+        let mut body = serde_json::json!({
+            "contents": [{ "role": "user", "parts": [{ "text": request.prompt }] }],
+            "generationConfig": { "responseModalities": ["TEXT", "IMAGE"] },
+        });
+        self.add_safety_settings(&mut body)?;
+        let resp = self
+            .client
+            .execute(self.client.post(&url).json(&body))
+            .await?;
+        let gemini_resp: GeminiResponse = resp.json().await?;
+        let candidates = gemini_resp.candidates.unwrap_or_default();
+        if let Some(blocked) = blocked_error(gemini_resp.prompt_feedback.as_ref(), &candidates) {
+            return Err(blocked);
+        }
+        candidates
+            .into_iter()
+            .filter_map(|c| c.content)
+            .flat_map(|content| content.parts)
+            .filter_map(|p| p.inline_data)
+            .map(|inline| decode_image(&inline.data))
+            .collect()
     }
 
     async fn list_models(&self) -> Result<Vec<ModelInfo>, CrabError> {
@@ -145,14 +652,8 @@ impl Provider for GoogleProvider {
             Ok(k) => k,
             Err(_) => return Ok(Self::static_models()),
         };
-        let url = format!("{}/models?key={}", Self::BASE_URL, api_key);
-        let resp = self.client.get(&url).send().await?;
-
-        let status = resp.status();
-        if !status.is_success() {
-            // Don't error, just fall back to static list.
-            return Ok(Self::static_models());
-        }
+        let url = format!("{}/models?key={}", &self.base_url, api_key);
+        let resp = self.client.execute(self.client.get(&url)).await?;
 
         #[derive(Deserialize)]
         struct ModelsList {
@@ -164,28 +665,187 @@ impl Provider for GoogleProvider {
             name: String,
             #[serde(rename = "outputTokenLimit")]
             output_token_limit: Option<u32>,
+            #[serde(rename = "supportedGenerationMethods", default)]
+            supported_generation_methods: Vec<String>,
         }
 
-        match resp.json::<ModelsList>().await {
-            Ok(list) => {
-                let mut models: Vec<ModelInfo> = list
-                    .models
-                    .into_iter()
-                    .map(|m| {
-                        let id = m.name.strip_prefix("models/").unwrap_or(&m.name).to_string();
-                        let mut info = ModelInfo::new(&id);
-                        info.max_output_tokens = m.output_token_limit;
-                        info
-                    })
-                    .collect();
-                models.sort_by(|a, b| a.id.cmp(&b.id));
-                Ok(models)
-            }
-            Err(_) => Ok(Self::static_models()),
-        }
+        let mut models: Vec<ModelInfo> = resp
+            .json::<ModelsList>()
+            .await?
+            .models
+            .into_iter()
+            .map(|m| {
+                let id = m.name.strip_prefix("models/").unwrap_or(&m.name).to_string();
+                let mut info = ModelInfo::new(&id);
+                info.max_output_tokens = m.output_token_limit;
+                // Logprobs come with generateContent; embedding and Imagen
+                // models lack it. Whether a Gemini model enables
+                // responseLogprobs is not listed, so it stays unknown.
+                if !m.supported_generation_methods.iter().any(|g| g == "generateContent") {
+                    info.supports_logprobs = Some(false);
+                }
+                info
+            })
+            .collect();
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(models)
     }
 
     fn name(&self) -> &str {
         "google"
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            system_role: true,
+            streaming: true,
+            tools: true,
+            vision: true,
+            pdf: true,
+            json_schema: true,
+            reasoning: true,
+            prefill: false,
+            logprobs: true,
+            web_search: true,
+            temperature: Some(DEFAULT_TEMPERATURE_RANGE),
+            max_tokens_param: None,
+        }
+    }
+
+    fn model_capabilities(&self, model: &str) -> Capabilities {
+        let gemini = model.starts_with("gemini-");
+        // The original text-only Gemini 1.0 Pro lacks system instructions,
+        // JSON schemas and search grounding.
+        let modern = gemini && !model.starts_with("gemini-1.0") && model != "gemini-pro";
+        Capabilities {
+            system_role: modern,
+            tools: gemini,
+            vision: modern,
+            pdf: modern,
+            json_schema: modern,
+            reasoning: model.starts_with("gemini-2.5") || model.contains("thinking"),
+            web_search: modern,
+            ..self.capabilities()
+        }
+    }
+
+    fn image_options(&self, model: &str) -> Option<ImageOptions> {
+        if model.starts_with("imagen") {
+            Some(ImageOptions {
+                sizes: &["1:1", "3:4", "4:3", "9:16", "16:9"],
+                any_size: false,
+                qualities: &[],
+                max_count: 4,
+            })
+        } else if model.starts_with("gemini") && model.contains("image") {
+            Some(ImageOptions {
+                sizes: &[],
+                any_size: false,
+                qualities: &[],
+                max_count: 1,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes base64 image data returned by Gemini or Imagen.
+fn decode_image(data: &str) -> Result<GeneratedImage, CrabError> {
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map(GeneratedImage)
+        .map_err(|e| CrabError::ProviderError {
+            provider: "google".to_string(),
+            message: format!("Invalid image data: {e}"),
+        })
+}
+
+/// Pairs each chosen token with the top candidates at the same position.
+fn token_logprobs(result: LogprobsResult) -> Vec<TokenLogprob> {
+    let mut top = result.top_candidates.into_iter();
+    result
+        .chosen_candidates
+        .into_iter()
+        .map(|chosen| TokenLogprob {
+            token: chosen.token,
+            logprob: chosen.log_probability,
+            top_logprobs: top
+                .next()
+                .map(|t| {
+                    t.candidates
+                        .into_iter()
+                        .map(|c| TopLogprob {
+                            token: c.token,
+                            logprob: c.log_probability,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocked_message(response: serde_json::Value) -> Option<String> {
+        let response: GeminiResponse = serde_json::from_value(response).unwrap();
+        let candidates = response.candidates.unwrap_or_default();
+        match blocked_error(response.prompt_feedback.as_ref(), &candidates)? {
+            CrabError::ContentFiltered(e) => Some(e.message),
+            other => panic!("expected ContentFiltered, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn blocked_prompt_names_flagged_categories() {
+        let message = blocked_message(serde_json::json!({
+            "promptFeedback": {
+                "blockReason": "SAFETY",
+                "blockReasonMessage": "The prompt violates the policy.",
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE"},
+                    {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH",
+                     "blocked": true}
+                ]
+            }
+        }));
+        assert_eq!(
+            message.as_deref(),
+            Some(
+                "Prompt blocked (SAFETY): HARM_CATEGORY_DANGEROUS_CONTENT (HIGH). \
+                 The prompt violates the policy."
+            )
+        );
+    }
+
+    #[test]
+    fn blocked_answer_falls_back_to_likely_categories() {
+        let message = blocked_message(serde_json::json!({
+            "candidates": [{
+                "finishReason": "SAFETY",
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_HATE_SPEECH", "probability": "MEDIUM"},
+                    {"category": "HARM_CATEGORY_HARASSMENT", "probability": "LOW"}
+                ]
+            }]
+        }));
+        assert_eq!(
+            message.as_deref(),
+            Some("Answer blocked (SAFETY): HARM_CATEGORY_HATE_SPEECH (MEDIUM)")
+        );
+    }
+
+    #[test]
+    fn finished_answer_is_not_blocked() {
+        let message = blocked_message(serde_json::json!({
+            "candidates": [{
+                "content": {"parts": [{"text": "Hello"}]},
+                "finishReason": "STOP"
+            }]
+        }));
+        assert_eq!(message, None);
+    }
 }
@@ -1,31 +1,37 @@
 use async_trait::async_trait;
-use reqwest::Client;
 
 use super::openai_compat;
+use super::http::HttpClient;
 use super::r#trait::Provider;
+use crate::api_key;
+use crate::config::Config;
 use crate::error::CrabError;
-use crate::types::ModelInfo;
+use crate::types::{
+    Capabilities, ChatRequest, Completion, ModelInfo, SpeechRequest, TranscriptionRequest,
+};
 
 /// Groq inference API. OpenAI-compatible.
 pub struct GroqProvider {
-    client: Client,
-    api_key: Option<String>,
+    client: HttpClient,
+    base_url: String,
+    api_keys: api_key::Keys,
 }
 
 impl GroqProvider {
     const BASE_URL: &'static str = "https://api.groq.com/openai/v1";
 
-    /// Creates a new provider instance with a custom environment variable name.
-    pub fn new_with_env(env_var: &str) -> Result<Self, CrabError> {
+    /// Creates a new provider instance from the application config.
+    pub fn new(config: &Config) -> Result<Self, CrabError> {
         Ok(Self {
-            client: Client::new(),
-            api_key: std::env::var(env_var).ok(),
+            client: HttpClient::new("groq", config)?,
+            base_url: config.provider_base_url("groq", Self::BASE_URL),
+            api_keys: config.api_key_sources("groq")?,
         })
     }
 
     fn require_key(&self) -> Result<&str, CrabError> {
-        self.api_key
-            .as_deref()
+        self.api_keys
+            .active()?
             .ok_or_else(|| CrabError::MissingApiKey("groq".to_string()))
     }
 
@@ -50,24 +56,37 @@ impl GroqProvider {
 
 #[async_trait]
 impl Provider for GroqProvider {
-    async fn send(
-        &self,
-        model: &str,
-        prompt: &str,
-        temperature: Option<f32>,
-        max_tokens: u32,
-        max_tokens_key: Option<String>,
-    ) -> Result<String, CrabError> {
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
         let api_key = self.require_key()?;
         openai_compat::send_chat_request(
             &self.client,
-            Self::BASE_URL,
+            &self.base_url,
             api_key,
-            model,
-            prompt,
-            temperature,
-            max_tokens,
-            max_tokens_key,
+            request,
+        )
+        .await
+    }
+
+    async fn transcribe(&self, request: &TranscriptionRequest) -> Result<String, CrabError> {
+        let api_key = self.require_key()?;
+        openai_compat::post_transcription(
+            &self.client,
+            &self.base_url,
+            api_key,
+            request,
+            "whisper-large-v3-turbo",
+        )
+        .await
+    }
+
+    async fn speak(&self, request: &SpeechRequest) -> Result<Vec<u8>, CrabError> {
+        let api_key = self.require_key()?;
+        openai_compat::post_speech(
+            &self.client,
+            &self.base_url,
+            api_key,
+            request,
+            "Fritz-PlayAI",
         )
         .await
     }
@@ -77,13 +96,32 @@ impl Provider for GroqProvider {
             Ok(k) => k,
             Err(_) => return Ok(Self::static_models()),
         };
-        match openai_compat::list_models_api(&self.client, Self::BASE_URL, api_key).await {
-            Ok(models) => Ok(models),
-            Err(_) => Ok(Self::static_models()),
-        }
+        openai_compat::list_models_api(&self.client, &self.base_url, api_key).await
     }
 
     fn name(&self) -> &str {
         "groq"
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            system_role: true,
+            streaming: true,
+            tools: true,
+            vision: true,
+            reasoning: true,
+            prefill: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn model_capabilities(&self, model: &str) -> Capabilities {
+        Capabilities {
+            vision: model.contains("llama-4") || model.contains("vision"),
+            reasoning: ["deepseek-r1", "qwq", "gpt-oss"]
+                .iter()
+                .any(|name| model.contains(name)),
+            ..self.capabilities()
+        }
+    }
 }
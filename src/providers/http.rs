@@ -0,0 +1,251 @@
+//! HTTP client shared by all provider implementations.
+//! Wraps reqwest and applies the extra headers and query parameters
+//! configured under [advanced.headers.<provider>] and
+//! [advanced.query.<provider>] to every request, including model listing,
+//! turns failed responses into typed errors, and tracks the rate-limit
+//! quota reported in response headers. With several API keys, a request
+//! that hits a rate limit is sent again with the next key. Traffic is also
+//! recorded to, or replayed from, cassette files with --record / --replay.
+
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Request, RequestBuilder, Response, StatusCode};
+
+use super::errors;
+use crate::api_key;
+use crate::cassette::{self, Exchange};
+use crate::config::Config;
+use crate::error::CrabError;
+use crate::rate_limit::{self, Quota};
+
+pub struct HttpClient {
+    client: Client,
+    provider: String,
+    query: Vec<(String, String)>,
+    /// Longest pre-emptive wait for a quota reset; None disables waiting.
+    max_quota_wait: Option<Duration>,
+}
+
+impl HttpClient {
+    /// Builds a client for `provider` from the application config.
+    ///
+    /// Configured headers are installed as client defaults, so a header that
+    /// crabai sets itself for the provider's API contract (for example
+    /// `anthropic-version`) takes precedence over a configured one.
+    pub fn new(provider: &str, config: &Config) -> Result<Self, CrabError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in config.provider_headers(provider)? {
+            let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                CrabError::ConfigError(format!("Invalid header name for {provider}: {name}"))
+            })?;
+            let header_value = HeaderValue::from_str(&value).map_err(|_| {
+                CrabError::ConfigError(format!("Invalid value for {provider} header {name}"))
+            })?;
+            headers.insert(header_name, header_value);
+        }
+
+        Ok(Self {
+            client: Client::builder().default_headers(headers).build()?,
+            provider: provider.to_string(),
+            query: config.provider_query(provider)?,
+            max_quota_wait: config
+                .rate_limit_wait_enabled()
+                .then(|| Duration::from_secs(config.rate_limit_max_wait_secs())),
+        })
+    }
+
+    /// Lowercase name of the provider this client talks to.
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.with_query(self.client.get(url))
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.with_query(self.client.post(url))
+    }
+
+    /// GET of a URL the provider returned, such as a generated image. The
+    /// configured query parameters belong to the API and are not added.
+    pub fn download(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    /// Sends a request built with `get`/`post`. A non-success status is
+    /// converted into the matching typed error (Auth, RateLimited, ...),
+    /// parsed from the provider's JSON error body.
+    ///
+    /// If the last known quota for the request's API key is exhausted, or the
+    /// response is a rate limit or quota error, switches to the provider's
+    /// next API key that is not cooling down (see `api_key::rotate`). Without
+    /// one, waits for the quota reset before sending, or returns the error.
+    /// The quota reported by the response is saved.
+    ///
+    /// When replaying, the recorded response is returned instead and the
+    /// network and quota are left alone. Keys are not rotated while
+    /// recording, so the cassette holds the exchanges a replay will ask for.
+    pub async fn execute(&self, request: RequestBuilder) -> Result<Response, CrabError> {
+        let mut request = request.build()?;
+        loop {
+            let api_key = request_api_key(&request).map(str::to_string);
+            let recording = match cassette::mode() {
+                Some(cassette::Mode::Replay(dir)) => {
+                    let exchange = Exchange::new(&self.provider, &request, api_key.as_deref());
+                    return self.check_status(cassette::replay(dir, exchange)?).await;
+                }
+                Some(cassette::Mode::Record(dir)) => Some((
+                    dir,
+                    Exchange::new(&self.provider, &request, api_key.as_deref()),
+                )),
+                None => None,
+            };
+            let rotating = recording.is_none();
+            let rotate = |key: &str, cooldown: Duration| {
+                rotating
+                    .then(|| api_key::rotate(&self.provider, key, cooldown))
+                    .flatten()
+            };
+
+            let key_id = api_key.as_deref().map(rate_limit::key_fingerprint);
+            if let (Some(key), Some(key_id)) = (&api_key, &key_id) {
+                if let Some(wait) = self.quota_wait(key_id) {
+                    if let Some(next) = rotate(key, wait) {
+                        request = with_api_key(request, key, &next);
+                        continue;
+                    }
+                    self.wait_for_quota(wait).await;
+                }
+            }
+
+            let retry = request.try_clone();
+            let mut resp = self.client.execute(request).await?;
+            if let Some(key_id) = &key_id {
+                self.record_quota(key_id, resp.status(), resp.headers());
+            }
+            if let Some((dir, exchange)) = recording {
+                resp = cassette::record(dir, exchange, resp, api_key.as_deref()).await?;
+            }
+            match self.check_status(resp).await {
+                Err(CrabError::RateLimited(error)) => {
+                    let cooldown = Duration::from_secs(error.retry_after.unwrap_or(60));
+                    let next = api_key.as_deref().zip(retry).and_then(|(key, retry)| {
+                        rotate(key, cooldown).map(|next| with_api_key(retry, key, &next))
+                    });
+                    match next {
+                        Some(next) => request = next,
+                        None => return Err(CrabError::RateLimited(error)),
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Passes a successful response through; converts any other into the
+    /// matching typed error.
+    async fn check_status(&self, resp: Response) -> Result<Response, CrabError> {
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(resp);
+        }
+        let headers = resp.headers().clone();
+        let body = resp.text().await.unwrap_or_default();
+        Err(errors::from_response(&self.provider, status, &headers, &body))
+    }
+
+    /// How long until the last known quota for the key resets, if it is
+    /// exhausted.
+    fn quota_wait(&self, key_id: &str) -> Option<Duration> {
+        rate_limit::quota(&self.provider, key_id).and_then(|quota| quota.wait_time())
+    }
+
+    async fn wait_for_quota(&self, wait: Duration) {
+        let Some(max_wait) = self.max_quota_wait else {
+            return;
+        };
+        let wait = wait.min(max_wait);
+        eprintln!(
+            "Rate limit for {} exhausted; waiting {:.1}s for reset...",
+            self.provider,
+            wait.as_secs_f64()
+        );
+        tokio::time::sleep(wait).await;
+    }
+
+    fn record_quota(&self, key_id: &str, status: StatusCode, headers: &HeaderMap) {
+        let retry_after = headers
+            .get("retry-after")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<f64>().ok());
+        let quota = match retry_after {
+            Some(secs) if status == StatusCode::TOO_MANY_REQUESTS => {
+                Some(Quota::exhausted_for(secs.ceil() as u64))
+            }
+            _ => Quota::from_headers(headers),
+        };
+        let Some(quota) = quota else {
+            return;
+        };
+
+        rate_limit::record_observed(&self.provider, &quota);
+        rate_limit::update(&self.provider, key_id, quota);
+    }
+
+    fn with_query(&self, builder: RequestBuilder) -> RequestBuilder {
+        if self.query.is_empty() {
+            builder
+        } else {
+            builder.query(&self.query)
+        }
+    }
+}
+
+/// Extracts the API key from a built request: a bearer token, an
+/// `x-api-key`/`x-goog-api-key` header, or a `key` query parameter.
+fn request_api_key(request: &Request) -> Option<&str> {
+    let headers = request.headers();
+    if let Some(token) = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return Some(token);
+    }
+    if let Some(key) = ["x-api-key", "x-goog-api-key"]
+        .iter()
+        .find_map(|h| headers.get(*h))
+        .and_then(|v| v.to_str().ok())
+    {
+        return Some(key);
+    }
+    request
+        .url()
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("key="))
+}
+
+/// Replaces the API key `old` with `new` in a built request's headers and
+/// query string.
+fn with_api_key(mut request: Request, old: &str, new: &str) -> Request {
+    for value in request.headers_mut().values_mut() {
+        let Ok(text) = value.to_str() else {
+            continue;
+        };
+        if text.contains(old) {
+            if let Ok(mut replaced) = HeaderValue::from_str(&text.replace(old, new)) {
+                replaced.set_sensitive(value.is_sensitive());
+                *value = replaced;
+            }
+        }
+    }
+    let url = request.url_mut();
+    if let Some(query) = url.query().filter(|q| q.contains(old)) {
+        let query = query.replace(old, new);
+        url.set_query(Some(&query));
+    }
+    request
+}
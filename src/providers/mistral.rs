@@ -1,31 +1,35 @@
 use async_trait::async_trait;
-use reqwest::Client;
 
 use super::openai_compat;
+use super::http::HttpClient;
 use super::r#trait::Provider;
+use crate::api_key;
+use crate::config::Config;
 use crate::error::CrabError;
-use crate::types::ModelInfo;
+use crate::types::{Capabilities, ChatRequest, Completion, EmbedRequest, FimRequest, ModelInfo};
 
 /// Mistral AI inference API. OpenAI-compatible.
 pub struct MistralProvider {
-    client: Client,
-    api_key: Option<String>,
+    client: HttpClient,
+    base_url: String,
+    api_keys: api_key::Keys,
 }
 
 impl MistralProvider {
     const BASE_URL: &'static str = "https://api.mistral.ai/v1";
 
-    /// Creates a new provider instance with a custom environment variable name.
-    pub fn new_with_env(env_var: &str) -> Result<Self, CrabError> {
+    /// Creates a new provider instance from the application config.
+    pub fn new(config: &Config) -> Result<Self, CrabError> {
         Ok(Self {
-            client: Client::new(),
-            api_key: std::env::var(env_var).ok(),
+            client: HttpClient::new("mistral", config)?,
+            base_url: config.provider_base_url("mistral", Self::BASE_URL),
+            api_keys: config.api_key_sources("mistral")?,
         })
     }
 
     fn require_key(&self) -> Result<&str, CrabError> {
-        self.api_key
-            .as_deref()
+        self.api_keys
+            .active()?
             .ok_or_else(|| CrabError::MissingApiKey("mistral".to_string()))
     }
 
@@ -48,24 +52,35 @@ impl MistralProvider {
 
 #[async_trait]
 impl Provider for MistralProvider {
-    async fn send(
-        &self,
-        model: &str,
-        prompt: &str,
-        temperature: Option<f32>,
-        max_tokens: u32,
-        max_tokens_key: Option<String>,
-    ) -> Result<String, CrabError> {
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
         let api_key = self.require_key()?;
-        openai_compat::send_chat_request(
+        let mut body = openai_compat::chat_body(request);
+        openai_compat::mark_prefix(&mut body);
+        openai_compat::post_chat(&self.client, &self.base_url, api_key, &body).await
+    }
+
+    async fn send_n(&self, request: &ChatRequest, n: u32) -> Result<Vec<Completion>, CrabError> {
+        let api_key = self.require_key()?;
+        let mut body = openai_compat::chat_body(request);
+        openai_compat::mark_prefix(&mut body);
+        openai_compat::set_n(&mut body, n);
+        openai_compat::post_chat_n(&self.client, &self.base_url, api_key, &body).await
+    }
+
+    async fn complete_fim(&self, request: &FimRequest) -> Result<Completion, CrabError> {
+        let url = format!("{}/fim/completions", &self.base_url);
+        openai_compat::post_fim(&self.client, &url, self.require_key()?, request).await
+    }
+
+    async fn embed(&self, request: &EmbedRequest) -> Result<Vec<Vec<f32>>, CrabError> {
+        let api_key = self.require_key()?;
+        let dimensions_key = Some("output_dimension");
+        openai_compat::post_embeddings(
             &self.client,
-            Self::BASE_URL,
+            &self.base_url,
             api_key,
-            model,
-            prompt,
-            temperature,
-            max_tokens,
-            max_tokens_key,
+            request,
+            dimensions_key,
         )
         .await
     }
@@ -75,13 +90,21 @@ impl Provider for MistralProvider {
             Ok(k) => k,
             Err(_) => return Ok(Self::static_models()),
         };
-        match openai_compat::list_models_api(&self.client, Self::BASE_URL, api_key).await {
-            Ok(models) => Ok(models),
-            Err(_) => Ok(Self::static_models()),
-        }
+        openai_compat::list_models_api(&self.client, &self.base_url, api_key).await
     }
 
     fn name(&self) -> &str {
         "mistral"
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            system_role: true,
+            streaming: true,
+            tools: true,
+            json_schema: true,
+            prefill: true,
+            ..Capabilities::default()
+        }
+    }
 }
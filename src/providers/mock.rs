@@ -0,0 +1,407 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use super::errors;
+use super::r#trait::Provider;
+use crate::config::Config;
+use crate::error::CrabError;
+use crate::types::{Capabilities, ChatRequest, Completion, FinishReason, ModelInfo, Role};
+
+/// Built-in offline provider for scripting and tests. Needs no API key or
+/// network; the model name selects the behavior (`mock:echo`,
+/// `mock:fixtures`, `mock:slow-500`, ...). Simulated failures are built from
+/// provider-style HTTP error responses, so they surface as the same typed
+/// errors and exit codes as real ones.
+pub struct MockProvider {
+    fixtures_path: PathBuf,
+}
+
+/// Simulated latency when `slow` has no duration suffix.
+const DEFAULT_LATENCY_MS: u64 = 1000;
+
+/// Error kinds of `mock:error-<kind>`, with the HTTP status and provider
+/// error code they are simulated with.
+const ERRORS: &[(&str, u16, &str, &str)] = &[
+    ("auth", 401, "invalid_api_key", "Incorrect API key provided"),
+    (
+        "rate-limit",
+        429,
+        "rate_limit_exceeded",
+        "Rate limit reached for requests",
+    ),
+    (
+        "context",
+        400,
+        "context_length_exceeded",
+        "This model's maximum context length was exceeded",
+    ),
+    (
+        "invalid",
+        400,
+        "invalid_request_error",
+        "Invalid request parameter",
+    ),
+    (
+        "filter",
+        400,
+        "content_filter",
+        "The prompt was flagged by the content filter",
+    ),
+    (
+        "server",
+        500,
+        "server_error",
+        "The server had an error processing the request",
+    ),
+    ("model", 404, "model_not_found", "The model does not exist"),
+];
+
+/// What a mock model does with a request.
+enum Mode {
+    /// Answer with the prompt.
+    Echo,
+    /// Answer with the first fixture whose pattern matches the prompt.
+    Fixtures,
+    /// Echo after a delay.
+    Slow(Duration),
+    /// Echo at most `max_tokens` words at a time, reporting a length stop
+    /// reason until the whole prompt has been returned.
+    Truncate,
+    /// Fail with a simulated provider error.
+    Error(&'static str),
+}
+
+impl Mode {
+    fn parse(model: &str) -> Result<Self, CrabError> {
+        let (name, arg) = match model.split_once('-') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (model, None),
+        };
+        let millis = |default: u64| -> Option<Duration> {
+            match arg {
+                None => Some(Duration::from_millis(default)),
+                Some(ms) => ms.parse().ok().map(Duration::from_millis),
+            }
+        };
+        let mode = match (name, arg) {
+            ("echo", None) => Some(Mode::Echo),
+            ("fixtures", None) => Some(Mode::Fixtures),
+            ("truncate", None) => Some(Mode::Truncate),
+            ("slow", _) => millis(DEFAULT_LATENCY_MS).map(Mode::Slow),
+            ("error", Some(kind)) => ERRORS
+                .iter()
+                .find(|(k, ..)| *k == kind)
+                .map(|(k, ..)| Mode::Error(k)),
+            _ => None,
+        };
+        mode.ok_or_else(|| {
+            simulated_error(
+                404,
+                "model_not_found",
+                &format!("The model `{model}` does not exist"),
+            )
+        })
+    }
+}
+
+/// Canned responses of `mock:fixtures`.
+#[derive(Deserialize)]
+struct FixtureFile {
+    #[serde(default)]
+    fixtures: Vec<Fixture>,
+}
+
+#[derive(Deserialize)]
+struct Fixture {
+    /// Regular expression searched for in the prompt.
+    pattern: String,
+    #[serde(default)]
+    response: String,
+    /// Error kind to fail with instead of responding (as in `error-<kind>`).
+    error: Option<String>,
+}
+
+impl MockProvider {
+    /// Creates a new provider instance from the application config.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            fixtures_path: config.mock_fixtures_path(),
+        }
+    }
+
+    /// Answers `prompt` from the fixtures file.
+    fn fixture_response(&self, prompt: &str) -> Result<String, CrabError> {
+        let path = self.fixtures_path.display();
+        let contents = std::fs::read_to_string(&self.fixtures_path).map_err(|e| {
+            CrabError::ConfigError(format!("Cannot read mock fixtures {path}: {e}"))
+        })?;
+        let file: FixtureFile = toml::from_str(&contents)?;
+
+        for fixture in file.fixtures {
+            let pattern = Regex::new(&fixture.pattern).map_err(|e| {
+                CrabError::ConfigError(format!("Invalid fixture pattern in {path}: {e}"))
+            })?;
+            if !pattern.is_match(prompt) {
+                continue;
+            }
+            return match fixture.error {
+                Some(kind) => Err(error_kind(&kind).ok_or_else(|| {
+                    CrabError::ConfigError(format!("Unknown fixture error kind in {path}: {kind}"))
+                })?),
+                None => Ok(fixture.response),
+            };
+        }
+        Err(CrabError::ProviderError {
+            provider: "mock".to_string(),
+            message: format!("No fixture in {path} matches the prompt"),
+        })
+    }
+}
+
+/// The simulated error for an `error-<kind>` kind, None if unknown.
+fn error_kind(kind: &str) -> Option<CrabError> {
+    ERRORS
+        .iter()
+        .find(|(k, ..)| *k == kind)
+        .map(|(_, status, code, message)| simulated_error(*status, code, message))
+}
+
+/// Classifies an OpenAI-style error response, as a real provider's would be.
+fn simulated_error(status: u16, code: &str, message: &str) -> CrabError {
+    let mut headers = HeaderMap::new();
+    if status == 429 {
+        headers.insert("retry-after", HeaderValue::from_static("1"));
+    }
+    let body = serde_json::json!({
+        "error": { "message": format!("{message} (simulated)"), "code": code }
+    });
+    errors::from_response(
+        "mock",
+        StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+        &headers,
+        &body.to_string(),
+    )
+}
+
+/// Splits text into words, each with the whitespace that follows it.
+fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut in_space = false;
+    for (i, c) in text.char_indices() {
+        if !c.is_whitespace() && in_space {
+            words.push(&text[start..i]);
+            start = i;
+        }
+        in_space = c.is_whitespace();
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+fn completion(text: String, finish_reason: FinishReason) -> Completion {
+    Completion {
+        output_tokens: Some(words(&text).len() as u32),
+        text,
+        finish_reason: Some(finish_reason),
+        logprobs: None,
+        sources: Vec::new(),
+        id: None,
+        upstream_provider: None,
+    }
+}
+
+#[async_trait]
+impl Provider for MockProvider {
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
+        let mode = Mode::parse(&request.model)?;
+        // The assembled prompt is the latest user turn.
+        let prompt = request
+            .messages
+            .iter()
+            .rev()
+            .find(|m| m.role == Role::User)
+            .map(|m| m.content.clone())
+            .unwrap_or_default();
+
+        match mode {
+            Mode::Echo => Ok(completion(prompt, FinishReason::Stop)),
+            Mode::Fixtures => Ok(completion(
+                self.fixture_response(&prompt)?,
+                FinishReason::Stop,
+            )),
+            Mode::Slow(latency) => {
+                tokio::time::sleep(latency).await;
+                Ok(completion(prompt, FinishReason::Stop))
+            }
+            Mode::Truncate => {
+                // Continuation requests end with a follow-up turn; the text
+                // to return is the original prompt, resumed after the words
+                // already answered.
+                let original = request
+                    .messages
+                    .iter()
+                    .find(|m| m.role == Role::User)
+                    .map(|m| m.content.as_str())
+                    .unwrap_or_default();
+                let answered: usize = request
+                    .messages
+                    .iter()
+                    .filter(|m| m.role == Role::Assistant)
+                    .map(|m| words(&m.content).len())
+                    .sum();
+                let original = words(original);
+                let remaining = &original[answered.min(original.len())..];
+                let limit = request.max_tokens.max(1) as usize;
+                let finish_reason = if remaining.len() > limit {
+                    FinishReason::Length
+                } else {
+                    FinishReason::Stop
+                };
+                let text = remaining.iter().take(limit).copied().collect();
+                Ok(completion(text, finish_reason))
+            }
+            Mode::Error(kind) => Err(error_kind(kind).expect("known error kind")),
+        }
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, CrabError> {
+        let modes = ["echo", "fixtures", "slow", "truncate"];
+        Ok(modes
+            .iter()
+            .map(|m| m.to_string())
+            .chain(ERRORS.iter().map(|(kind, ..)| format!("error-{kind}")))
+            .map(|id| ModelInfo::new(&id))
+            .collect())
+    }
+
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            system_role: true,
+            prefill: true,
+            ..Capabilities::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::continuation;
+    use crate::types::ChatMessage;
+
+    fn provider() -> MockProvider {
+        MockProvider {
+            fixtures_path: PathBuf::from("/nonexistent/mock_fixtures.toml"),
+        }
+    }
+
+    async fn ask(mock: &MockProvider, model: &str, prompt: &str) -> Result<Completion, CrabError> {
+        mock.send(&request(model, prompt)).await
+    }
+
+    fn request(model: &str, prompt: &str) -> ChatRequest {
+        ChatRequest {
+            model: model.to_string(),
+            messages: vec![ChatMessage::user(prompt)],
+            temperature: None,
+            max_tokens: 3,
+            max_tokens_key: None,
+            omit_params: Vec::new(),
+            prefill: None,
+            logprobs: None,
+            web_search: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn echo_returns_the_prompt() {
+        let completion = ask(&provider(), "echo", "hello there").await.unwrap();
+        assert_eq!(completion.text, "hello there");
+        assert_eq!(completion.finish_reason, Some(FinishReason::Stop));
+        assert_eq!(completion.output_tokens, Some(2));
+    }
+
+    #[tokio::test]
+    async fn fixtures_answer_with_first_matching_pattern() {
+        let fixtures_path =
+            std::env::temp_dir().join(format!("crabai-test-{}-fixtures.toml", std::process::id()));
+        std::fs::write(
+            &fixtures_path,
+            r#"
+            [[fixtures]]
+            pattern = "(?i)capital of france"
+            response = "Paris"
+
+            [[fixtures]]
+            pattern = "capital"
+            response = "Somewhere"
+
+            [[fixtures]]
+            pattern = "quota"
+            error = "rate-limit"
+            "#,
+        )
+        .unwrap();
+        let mock = MockProvider { fixtures_path };
+        let answer = |prompt| ask(&mock, "fixtures", prompt);
+
+        let paris = answer("What is the Capital of France?").await;
+        let peru = answer("capital of Peru?").await;
+        let quota = answer("quota please").await;
+        let unmatched = answer("unmatched").await;
+        std::fs::remove_file(&mock.fixtures_path).unwrap();
+
+        assert_eq!(paris.unwrap().text, "Paris");
+        assert_eq!(peru.unwrap().text, "Somewhere");
+        assert!(matches!(quota, Err(CrabError::RateLimited(_))));
+        assert!(matches!(unmatched, Err(CrabError::ProviderError { .. })));
+    }
+
+    #[tokio::test]
+    async fn error_modes_map_to_typed_errors() {
+        let mock = provider();
+        let fail = |model| ask(&mock, model, "hi");
+
+        assert!(matches!(fail("error-auth").await, Err(CrabError::Auth(_))));
+        assert!(matches!(
+            fail("error-rate-limit").await,
+            Err(CrabError::RateLimited(e)) if e.retry_after == Some(1)
+        ));
+        assert!(matches!(fail("error-context").await, Err(CrabError::ContextLength(_))));
+        assert!(matches!(fail("error-invalid").await, Err(CrabError::InvalidParameter(_))));
+        assert!(matches!(fail("error-filter").await, Err(CrabError::ContentFiltered(_))));
+        assert!(matches!(fail("error-server").await, Err(CrabError::ServerError(_))));
+        assert!(matches!(fail("error-model").await, Err(CrabError::ModelNotFound(_))));
+        assert!(matches!(fail("stream").await, Err(CrabError::ModelNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn truncate_is_completed_by_continuation() {
+        let mock = provider();
+        let request = request("truncate", "one two three four five six seven");
+        let first = mock.send(&request).await.unwrap();
+        assert_eq!(first.text, "one two three ");
+        assert_eq!(first.finish_reason, Some(FinishReason::Length));
+
+        let continued = continuation::continue_truncated(&mock, &request, first, 5)
+            .await
+            .unwrap();
+        assert_eq!(continued.rounds, 2);
+        assert_eq!(continued.completion.text, "one two three four five six seven");
+        assert_eq!(continued.completion.finish_reason, Some(FinishReason::Stop));
+        assert_eq!(continued.completion.output_tokens, Some(7));
+    }
+}
@@ -1,3 +1,5 @@
+mod errors;
+mod http;
 mod openai_compat;
 pub mod r#trait;
 
@@ -6,15 +8,17 @@ pub mod deepseek;
 pub mod google;
 pub mod groq;
 pub mod mistral;
+pub mod mock;
 pub mod openai;
 pub mod openrouter;
+pub mod plugin;
 pub mod together;
 
 pub use r#trait::Provider;
 
 use crate::config::Config;
 use crate::error::CrabError;
-use crate::types::ProviderName;
+use crate::types::{ChatRequest, Completion, ProviderName};
 
 /// Creates a provider instance by name using default API key environment variables.
 ///
@@ -29,11 +33,13 @@ pub fn get_provider(name: &str) -> Result<Box<dyn Provider>, CrabError> {
     get_provider_with_config(name, &Config::default())
 }
 
-/// Creates a provider instance with custom API key environment variable lookup.
+/// Creates a provider instance configured from the application config.
+/// Names that are not built-in providers resolve to provider plugins
+/// (see `plugin`).
 ///
 /// Uses the provided config to determine which environment variable to read for
-/// the provider's API key. This allows users to customize env var names via the
-/// advanced.api_key_vars config section.
+/// the provider's API key (advanced.api_key_vars) and which extra headers and
+/// query parameters to send with every request (advanced.headers, advanced.query).
 ///
 /// The API key is not validated during construction; validation happens lazily
 /// when send() or list_models() is called.
@@ -41,55 +47,81 @@ pub fn get_provider_with_config(
     name: &str,
     config: &Config,
 ) -> Result<Box<dyn Provider>, CrabError> {
-    let provider_name: ProviderName = name
-        .parse()
-        .map_err(|e: String| CrabError::ConfigError(e))?;
+    let provider_name: ProviderName = match name.parse() {
+        Ok(provider_name) => provider_name,
+        Err(e) => {
+            return match plugin::find(name, config) {
+                Some(path) => Ok(Box::new(plugin::PluginProvider::new(name, path))),
+                None => Err(CrabError::ConfigError(e)),
+            }
+        }
+    };
 
     match provider_name {
         ProviderName::OpenAI => Ok(Box::new(openai::OpenAIProvider::new(config)?)),
-        ProviderName::Anthropic => {
-            let api_key_var = config.api_key_var(name);
-            Ok(Box::new(anthropic::AnthropicProvider::new_with_env(
-                &api_key_var,
-            )?))
-        }
-        ProviderName::Google => {
-            let api_key_var = config.api_key_var(name);
-            Ok(Box::new(google::GoogleProvider::new_with_env(
-                &api_key_var,
-            )?))
-        }
-        ProviderName::OpenRouter => {
-            let api_key_var = config.api_key_var(name);
-            Ok(Box::new(openrouter::OpenRouterProvider::new_with_env(
-                &api_key_var,
-            )?))
-        }
-        ProviderName::Groq => {
-            let api_key_var = config.api_key_var(name);
-            Ok(Box::new(groq::GroqProvider::new_with_env(&api_key_var)?))
-        }
-        ProviderName::Together => {
-            let api_key_var = config.api_key_var(name);
-            Ok(Box::new(together::TogetherProvider::new_with_env(
-                &api_key_var,
-            )?))
-        }
-        ProviderName::Mistral => {
-            let api_key_var = config.api_key_var(name);
-            Ok(Box::new(mistral::MistralProvider::new_with_env(
-                &api_key_var,
-            )?))
-        }
-        ProviderName::DeepSeek => {
-            let api_key_var = config.api_key_var(name);
-            Ok(Box::new(deepseek::DeepSeekProvider::new_with_env(
-                &api_key_var,
-            )?))
+        ProviderName::Anthropic => Ok(Box::new(anthropic::AnthropicProvider::new(config)?)),
+        ProviderName::Google => Ok(Box::new(google::GoogleProvider::new(config)?)),
+        ProviderName::OpenRouter => Ok(Box::new(openrouter::OpenRouterProvider::new(config)?)),
+        ProviderName::Groq => Ok(Box::new(groq::GroqProvider::new(config)?)),
+        ProviderName::Together => Ok(Box::new(together::TogetherProvider::new(config)?)),
+        ProviderName::Mistral => Ok(Box::new(mistral::MistralProvider::new(config)?)),
+        ProviderName::DeepSeek => Ok(Box::new(deepseek::DeepSeekProvider::new(config)?)),
+        ProviderName::Mock => Ok(Box::new(mock::MockProvider::new(config))),
+    }
+}
+
+/// Returns `n` answers to `request`. Uses the provider's native support
+/// unless the model is known to reject `n`, and requests any answers the
+/// API did not return (some silently ignore `n`) separately in parallel.
+pub async fn send_many(
+    provider: &dyn Provider,
+    request: &ChatRequest,
+    n: u32,
+) -> Result<Vec<Completion>, CrabError> {
+    if n <= 1 {
+        return Ok(vec![provider.send(request).await?]);
+    }
+
+    let mut completions = if request.omit_params.iter().any(|p| p == "n") {
+        Vec::new()
+    } else {
+        provider.send_n(request, n).await?
+    };
+    let missing = n.saturating_sub(completions.len() as u32);
+    let more = futures::future::try_join_all((0..missing).map(|_| provider.send(request))).await?;
+    completions.extend(more);
+    completions.truncate(n as usize);
+    Ok(completions)
+}
+
+/// Removes parameters the model is known to reject from a JSON request body.
+/// Names are matched against top-level keys of `body`.
+pub(crate) fn omit_params(body: &mut serde_json::Value, params: &[String]) {
+    if let Some(obj) = body.as_object_mut() {
+        for param in params {
+            obj.remove(param);
         }
     }
 }
 
-pub fn list_provider_names() -> Vec<&'static str> {
-    ProviderName::ALL.iter().map(|p| p.as_str()).collect()
+/// Parses the custom id of a batch result back into the index of the
+/// request it answers.
+pub(crate) fn batch_index(provider: &str, custom_id: &str) -> Result<usize, CrabError> {
+    custom_id.parse().map_err(|_| CrabError::ProviderError {
+        provider: provider.to_string(),
+        message: format!("Unexpected custom_id in batch results: {custom_id}"),
+    })
+}
+
+/// Names of the built-in providers followed by those of the provider
+/// plugins declared in the config or found on PATH.
+pub fn list_provider_names(config: &Config) -> Vec<String> {
+    let plugins = plugin::names(config)
+        .into_iter()
+        .filter(|name| name.parse::<ProviderName>().is_err());
+    ProviderName::ALL
+        .iter()
+        .map(|p| p.to_string())
+        .chain(plugins)
+        .collect()
 }
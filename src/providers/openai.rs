@@ -1,17 +1,22 @@
 use async_trait::async_trait;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
-use super::openai_compat;
+use super::http::HttpClient;
 use super::r#trait::Provider;
+use super::{omit_params, openai_compat};
+use crate::api_key;
 use crate::config::Config;
 use crate::error::CrabError;
-
-use crate::types::ModelInfo;
+use crate::types::{
+    BatchResult, BatchStatus, Capabilities, ChatMessage, ChatRequest, Completion,
+    DEFAULT_TEMPERATURE_RANGE, EmbedRequest, FinishReason, GeneratedImage, ImageOptions,
+    ImageRequest, ModelInfo, Source, SpeechRequest, TranscriptionRequest,
+};
 
 pub struct OpenAIProvider {
-    client: Client,
-    api_key: Option<String>,
+    client: HttpClient,
+    base_url: String,
+    api_keys: api_key::Keys,
     max_tokens_param: String,
 }
 
@@ -20,7 +25,6 @@ impl OpenAIProvider {
 
     /// Creates a new provider instance from the application config.
     pub fn new(config: &Config) -> Result<Self, CrabError> {
-        let api_key_var = config.api_key_var("openai");
         let max_tokens_param = config
             .advanced
             .as_ref()
@@ -30,18 +34,92 @@ impl OpenAIProvider {
             .unwrap_or_else(|| "max_tokens".to_string());
 
         Ok(Self {
-            client: Client::new(),
-            api_key: std::env::var(api_key_var).ok(),
+            client: HttpClient::new("openai", config)?,
+            base_url: config.provider_base_url("openai", Self::BASE_URL),
+            api_keys: config.api_key_sources("openai")?,
             max_tokens_param,
         })
     }
 
     fn require_key(&self) -> Result<&str, CrabError> {
-        self.api_key
-            .as_deref()
+        self.api_keys
+            .active()?
             .ok_or_else(|| CrabError::MissingApiKey("openai".to_string()))
     }
 
+    /// Builds the chat request body with the max tokens parameter the model expects.
+    fn chat_body(&self, request: &ChatRequest) -> serde_json::Value {
+        // Use the explicitly provided key name, or the one the model needs.
+        let mut request = request.clone();
+        if request.max_tokens_key.is_none() {
+            let param = self.model_capabilities(&request.model).max_tokens_param;
+            request.max_tokens_key =
+                Some(param.map_or_else(|| self.max_tokens_param.clone(), str::to_string));
+        }
+        openai_compat::chat_body(&request)
+    }
+
+    /// Sends the request to the Responses API with the web search tool
+    /// enabled. Chat Completions only offers search on dedicated models.
+    async fn send_web(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
+        let input: Vec<serde_json::Value> = request
+            .messages
+            .iter()
+            .map(|m| serde_json::json!({ "role": m.role.as_str(), "content": input_content(m) }))
+            .collect();
+        let mut body = serde_json::json!({
+            "model": request.model,
+            "input": input,
+            "tools": [{ "type": "web_search" }],
+            "max_output_tokens": request.max_tokens,
+        });
+        if let Some(t) = request.temperature {
+            body["temperature"] = serde_json::json!(t);
+        }
+        omit_params(&mut body, &request.omit_params);
+
+        let url = format!("{}/responses", self.base_url);
+        let resp = self
+            .client
+            .execute(self.client.post(&url).bearer_auth(self.require_key()?).json(&body))
+            .await?;
+        let response: ResponsesResponse = resp.json().await?;
+
+        let mut completion = Completion {
+            text: String::new(),
+            finish_reason: match response.status.as_deref() {
+                Some("incomplete") => response
+                    .incomplete_details
+                    .and_then(|d| d.reason)
+                    .as_deref()
+                    .map(FinishReason::parse),
+                Some("completed") => Some(FinishReason::Stop),
+                other => other.map(FinishReason::parse),
+            },
+            output_tokens: response.usage.and_then(|u| u.output_tokens),
+            logprobs: None,
+            sources: Vec::new(),
+            id: None,
+            upstream_provider: None,
+        };
+        for part in response.output.into_iter().flat_map(|item| item.content) {
+            completion.text.push_str(part.text.as_deref().unwrap_or_default());
+            completion.add_sources(part.annotations.into_iter().filter_map(|a| {
+                Some(Source {
+                    url: a.url?,
+                    title: a.title,
+                })
+            }));
+        }
+        if completion.text.is_empty() {
+            return Err(CrabError::ProviderError {
+                provider: "openai".to_string(),
+                message: "Empty response from API".to_string(),
+            });
+        }
+        Ok(completion)
+    }
+
     fn static_models() -> Vec<ModelInfo> {
         // A comprehensive fallback list of common models.
         vec![
@@ -70,93 +148,158 @@ impl OpenAIProvider {
     }
 }
 
-// Custom request/response structs to avoid dependency on openai_compat's send method.
-#[derive(Serialize)]
-struct ChatMessage {
-    role: String,
-    content: String,
+/// Responses API message content: the text, or input parts with the
+/// attachments first.
+fn input_content(message: &ChatMessage) -> serde_json::Value {
+    if message.attachments.is_empty() {
+        return serde_json::json!(message.content);
+    }
+    let mut parts: Vec<serde_json::Value> = message
+        .attachments
+        .iter()
+        .map(|a| {
+            if a.is_pdf() {
+                serde_json::json!({
+                    "type": "input_file",
+                    "filename": a.file_name,
+                    "file_data": a.data_url(),
+                })
+            } else {
+                serde_json::json!({ "type": "input_image", "image_url": a.data_url() })
+            }
+        })
+        .collect();
+    if !message.content.is_empty() {
+        parts.push(serde_json::json!({ "type": "input_text", "text": message.content }));
+    }
+    serde_json::Value::Array(parts)
+}
+
+#[derive(Deserialize)]
+struct ResponsesResponse {
+    status: Option<String>,
+    incomplete_details: Option<IncompleteDetails>,
+    #[serde(default)]
+    output: Vec<OutputItem>,
+    usage: Option<ResponsesUsage>,
+}
+
+#[derive(Deserialize)]
+struct IncompleteDetails {
+    reason: Option<String>,
 }
 
+/// An output item: a message, or a record of a web search call (no content).
 #[derive(Deserialize)]
-struct ChatResponse {
-    choices: Vec<ChatChoice>,
+struct OutputItem {
+    #[serde(default)]
+    content: Vec<OutputContent>,
 }
 
 #[derive(Deserialize)]
-struct ChatChoice {
-    message: ChatResponseMessage,
+struct OutputContent {
+    text: Option<String>,
+    #[serde(default)]
+    annotations: Vec<OutputAnnotation>,
 }
 
 #[derive(Deserialize)]
-struct ChatResponseMessage {
-    content: String,
+struct OutputAnnotation {
+    url: Option<String>,
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResponsesUsage {
+    output_tokens: Option<u32>,
 }
 
 #[async_trait]
 impl Provider for OpenAIProvider {
-    async fn send(
-        &self,
-        model: &str,
-        prompt: &str,
-        temperature: Option<f32>,
-        max_tokens: u32,
-        max_tokens_key: Option<String>,
-    ) -> Result<String, CrabError> {
-        let mut request_body = serde_json::json!({
-            "model": model,
-            "messages": [
-                ChatMessage {
-                    role: "user".to_string(),
-                    content: prompt.to_string(),
-                }
-            ],
-        });
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
+        if request.web_search {
+            return self.send_web(request).await;
+        }
+        let body = self.chat_body(request);
+        openai_compat::post_chat(&self.client, &self.base_url, self.require_key()?, &body).await
+    }
 
-        if let Some(t) = temperature {
-            request_body["temperature"] = serde_json::json!(t);
+    async fn send_n(&self, request: &ChatRequest, n: u32) -> Result<Vec<Completion>, CrabError> {
+        if request.web_search {
+            // The Responses API has no `n`.
+            return futures::future::try_join_all((0..n).map(|_| self.send_web(request))).await;
         }
+        let mut body = self.chat_body(request);
+        openai_compat::set_n(&mut body, n);
+        openai_compat::post_chat_n(&self.client, &self.base_url, self.require_key()?, &body).await
+    }
 
-        // Use the explicitly provided key name, or determine the correct one based on the model.
-        let key = if let Some(k) = max_tokens_key {
-            k
-        } else if model.starts_with("o1") || model.starts_with("o3") || model == "gpt-5" {
-            "max_completion_tokens".to_string()
-        } else {
-            self.max_tokens_param.clone()
-        };
+    async fn embed(&self, request: &EmbedRequest) -> Result<Vec<Vec<f32>>, CrabError> {
+        let api_key = self.require_key()?;
+        let dimensions_key = Some("dimensions");
+        openai_compat::post_embeddings(
+            &self.client,
+            &self.base_url,
+            api_key,
+            request,
+            dimensions_key,
+        )
+        .await
+    }
 
-        // Add the max tokens parameter with the resolved name.
-        request_body[key] = serde_json::json!(max_tokens);
+    async fn transcribe(&self, request: &TranscriptionRequest) -> Result<String, CrabError> {
+        let api_key = self.require_key()?;
+        openai_compat::post_transcription(
+            &self.client,
+            &self.base_url,
+            api_key,
+            request,
+            "whisper-1",
+        )
+        .await
+    }
 
+    async fn generate_images(
+        &self,
+        request: &ImageRequest,
+    ) -> Result<Vec<GeneratedImage>, CrabError> {
+        let mut body = serde_json::json!({
+            "model": request.model,
+            "prompt": request.prompt,
+            "n": request.count,
+        });
+        if let Some(size) = &request.size {
+            body["size"] = serde_json::json!(size);
+        }
+        if let Some(quality) = &request.quality {
+            body["quality"] = serde_json::json!(quality);
+        }
+        // DALL-E returns URLs unless asked otherwise; gpt-image models always
+        // return base64 and reject the parameter.
+        if request.model.starts_with("dall-e") {
+            body["response_format"] = serde_json::json!("b64_json");
+        }
+        let api_key = self.require_key()?;
+        openai_compat::post_image_generation(&self.client, &self.base_url, api_key, &body).await
+    }
 
-        let url = format!("{}/chat/completions", Self::BASE_URL);
-        let resp = self
-            .client
-            .post(&url)
-            .bearer_auth(self.require_key()?)
-            .json(&request_body)
-            .send()
-            .await?;
+    async fn speak(&self, request: &SpeechRequest) -> Result<Vec<u8>, CrabError> {
+        let api_key = self.require_key()?;
+        openai_compat::post_speech(&self.client, &self.base_url, api_key, request, "alloy").await
+    }
 
-        let status = resp.status();
-        if !status.is_success() {
-            let body = resp.text().await.unwrap_or_default();
-            return Err(CrabError::ProviderError {
-                provider: url,
-                message: format!("HTTP {status}: {body}"),
-            });
-        }
+    async fn submit_batch(&self, requests: &[ChatRequest]) -> Result<BatchStatus, CrabError> {
+        let bodies: Vec<_> = requests.iter().map(|r| self.chat_body(r)).collect();
+        openai_compat::post_batch(&self.client, &self.base_url, self.require_key()?, &bodies).await
+    }
 
-        let chat_resp: ChatResponse = resp.json().await?;
-        chat_resp
-            .choices
-            .into_iter()
-            .next()
-            .map(|c| c.message.content)
-            .ok_or_else(|| CrabError::ProviderError {
-                provider: "openai".to_string(),
-                message: "Empty response".to_string(),
-            })
+    async fn batch_status(&self, id: &str) -> Result<BatchStatus, CrabError> {
+        openai_compat::get_batch(&self.client, &self.base_url, self.require_key()?, id).await
+    }
+
+    async fn fetch_batch(&self, id: &str) -> Result<Vec<BatchResult>, CrabError> {
+        let api_key = self.require_key()?;
+        openai_compat::get_batch_results(&self.client, &self.base_url, api_key, id).await
     }
 
     async fn list_models(&self) -> Result<Vec<ModelInfo>, CrabError> {
@@ -164,38 +307,108 @@ impl Provider for OpenAIProvider {
             Ok(k) => k,
             Err(_) => return Ok(Self::static_models()),
         };
-        match openai_compat::list_models_api(&self.client, Self::BASE_URL, api_key).await {
-            Ok(mut models) => {
-                // Apply known overrides for specific model families.
-                for m in &mut models {
-                    if m.id.starts_with("o1-") || m.id.starts_with("o3-") {
-                        m.supports_temperature = false;
-                        m.max_tokens_param = Some("max_completion_tokens".to_string());
-                    }
-                }
-                Ok(models)
+        let mut models =
+            openai_compat::list_models_api(&self.client, &self.base_url, api_key).await?;
+        // Record what the model descriptors know about each model.
+        for m in &mut models {
+            let capabilities = self.model_capabilities(&m.id);
+            if capabilities.temperature.is_none() {
+                m.supports_temperature = false;
+            }
+            if let Some(param) = capabilities.max_tokens_param {
+                m.max_tokens_param = Some(param.to_string());
+            }
+            if !capabilities.logprobs {
+                m.supports_logprobs = Some(false);
             }
-            Err(_) => Ok(Self::static_models()),
         }
+        Ok(models)
     }
 
     fn name(&self) -> &str {
         "openai"
     }
 
-    fn sanitize_params(&self, model: &str, temperature: f32, max_tokens: u32) -> (Option<f32>, u32) {
-        let mut final_temp = Some(temperature);
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            system_role: true,
+            streaming: true,
+            tools: true,
+            vision: true,
+            pdf: true,
+            json_schema: true,
+            reasoning: true,
+            prefill: false,
+            logprobs: true,
+            web_search: true,
+            temperature: Some(DEFAULT_TEMPERATURE_RANGE),
+            max_tokens_param: None,
+        }
+    }
+
+    fn model_capabilities(&self, model: &str) -> Capabilities {
+        let has_prefix = |prefixes: &[&str]| prefixes.iter().any(|p| model.starts_with(p));
+        let reasoning = has_prefix(&["o1", "o3", "o4", "gpt-5"]) && !model.contains("chat");
+        // The o1 previews take neither system messages nor tools.
+        let preview = has_prefix(&["o1-mini", "o1-preview"]);
+        let vision = has_prefix(&[
+            "gpt-4o",
+            "gpt-4.1",
+            "gpt-4-turbo",
+            "gpt-4-vision",
+            "gpt-5",
+            "o1",
+            "o3",
+            "o4",
+        ]) && !has_prefix(&["o1-mini", "o3-mini"]);
 
-        // Omit temperature for reasoning models (o1/o3) as it is unsupported or restricted.
-        if model.starts_with("o1") || model.starts_with("o3") {
-            final_temp = None;
-        } else if model == "gpt-5" {
-            // Force temperature to 1.0 for gpt-5.
-            final_temp = Some(1.0);
-        } else if model.starts_with("gpt-") && temperature > 1.0 {
-            final_temp = Some(1.0);
+        Capabilities {
+            system_role: !preview,
+            tools: !preview,
+            vision,
+            // File inputs are accepted by the vision models.
+            pdf: vision,
+            json_schema: has_prefix(&["gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4"]) && !preview,
+            reasoning,
+            // Reasoning models reject logprobs.
+            logprobs: !reasoning,
+            web_search: has_prefix(&["gpt-4o", "gpt-4.1", "gpt-5", "o3", "o4"]),
+            // o-series models reject a temperature; GPT-5 reasoning models
+            // only accept the default of 1.
+            temperature: if !reasoning {
+                Some(DEFAULT_TEMPERATURE_RANGE)
+            } else if model.starts_with("gpt-5") {
+                Some((1.0, 1.0))
+            } else {
+                None
+            },
+            // Reasoning models reject max_tokens.
+            max_tokens_param: reasoning.then_some("max_completion_tokens"),
+            ..self.capabilities()
         }
+    }
 
-        (final_temp, max_tokens)
+    fn image_options(&self, model: &str) -> Option<ImageOptions> {
+        match model {
+            "dall-e-2" => Some(ImageOptions {
+                sizes: &["256x256", "512x512", "1024x1024"],
+                any_size: false,
+                qualities: &[],
+                max_count: 10,
+            }),
+            "dall-e-3" => Some(ImageOptions {
+                sizes: &["1024x1024", "1792x1024", "1024x1792"],
+                any_size: false,
+                qualities: &["standard", "hd"],
+                max_count: 1,
+            }),
+            m if m.starts_with("gpt-image") => Some(ImageOptions {
+                sizes: &["auto", "1024x1024", "1536x1024", "1024x1536"],
+                any_size: false,
+                qualities: &["auto", "low", "medium", "high"],
+                max_count: 10,
+            }),
+            _ => None,
+        }
     }
 }
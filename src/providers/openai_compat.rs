@@ -1,31 +1,190 @@
 //! Shared request/response handling for OpenAI-compatible APIs.
-//! Used by: OpenAI, OpenRouter, Groq, Together, Mistral, DeepSeek.
+//!
+//! Endpoints and the providers that use them:
+//! - /chat/completions: OpenAI, OpenRouter, Groq, Together, Mistral, DeepSeek
+//! - fill-in-the-middle: Mistral, DeepSeek
+//! - /embeddings: OpenAI, Mistral, Together
+//! - /audio/transcriptions: OpenAI, Groq
+//! - /audio/speech: OpenAI, Groq, Together
+//! - /images/generations: OpenAI, Together
+//! - /files and /batches (Batch API): OpenAI
 
-use reqwest::Client;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 
+use super::http::HttpClient;
+use super::{batch_index, omit_params};
 use crate::error::CrabError;
-use crate::types::ModelInfo;
+use crate::types::{
+    BatchResult, BatchStatus, ChatMessage, ChatRequest, Completion, EmbedRequest, FimRequest,
+    FinishReason, GeneratedImage, ModelInfo, Role, Source, SpeechRequest, TokenLogprob, TopLogprob,
+    TranscriptionRequest,
+};
 
 #[derive(Serialize)]
 struct Message {
     role: String,
-    content: String,
+    /// Plain text, or an array of content parts when files are attached.
+    content: serde_json::Value,
 }
 
 #[derive(Deserialize)]
 struct ChatResponse {
+    id: Option<String>,
+    /// Upstream provider that served the request (OpenRouter).
+    provider: Option<String>,
     choices: Vec<Choice>,
+    usage: Option<Usage>,
 }
 
 #[derive(Deserialize)]
 struct Choice {
     message: ResponseMessage,
+    finish_reason: Option<String>,
+    logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Deserialize)]
+struct ChoiceLogprobs {
+    content: Option<Vec<LogprobEntry>>,
+}
+
+#[derive(Deserialize)]
+struct LogprobEntry {
+    token: String,
+    logprob: f64,
+    #[serde(default)]
+    top_logprobs: Vec<TopLogprobEntry>,
+}
+
+#[derive(Deserialize)]
+struct TopLogprobEntry {
+    token: String,
+    logprob: f64,
 }
 
 #[derive(Deserialize)]
 struct ResponseMessage {
-    content: String,
+    content: Option<String>,
+    /// URL citations of web-grounded answers (OpenRouter web plugin, OpenAI
+    /// search models).
+    annotations: Option<Vec<Annotation>>,
+}
+
+#[derive(Deserialize)]
+struct Annotation {
+    url_citation: Option<UrlCitation>,
+}
+
+#[derive(Deserialize)]
+struct UrlCitation {
+    url: String,
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FimResponse {
+    choices: Vec<FimChoice>,
+    usage: Option<Usage>,
+}
+
+/// A FIM choice carries the code either as legacy completions `text`
+/// (DeepSeek) or as a chat-style message (Mistral).
+#[derive(Deserialize)]
+struct FimChoice {
+    text: Option<String>,
+    message: Option<ResponseMessage>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    completion_tokens: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingEntry>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingEntry {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct ImagesResponse {
+    data: Vec<ImageEntry>,
+}
+
+#[derive(Deserialize)]
+struct ImageEntry {
+    b64_json: Option<String>,
+    url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FileObject {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct BatchObject {
+    id: String,
+    status: String,
+    #[serde(default)]
+    request_counts: Option<BatchCounts>,
+    output_file_id: Option<String>,
+    error_file_id: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct BatchCounts {
+    total: u32,
+    completed: u32,
+    failed: u32,
+}
+
+/// One line of a batch output or error file.
+#[derive(Deserialize)]
+struct BatchOutputLine {
+    custom_id: String,
+    response: Option<BatchResponse>,
+    error: Option<BatchError>,
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    status_code: u16,
+    body: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct BatchError {
+    message: String,
+}
+
+impl BatchObject {
+    fn status(self) -> BatchStatus {
+        let counts = self.request_counts.unwrap_or_default();
+        BatchStatus {
+            finished: matches!(
+                self.status.as_str(),
+                "completed" | "failed" | "expired" | "cancelled"
+            ),
+            id: self.id,
+            status: self.status,
+            total: counts.total,
+            succeeded: counts.completed,
+            failed: counts.failed,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -38,80 +197,511 @@ struct ModelEntry {
     id: String,
 }
 
-/// POST {base_url}/chat/completions with a single user message.
+/// POST {base_url}/chat/completions with the request messages.
 pub async fn send_chat_request(
-    client: &Client,
+    client: &HttpClient,
     base_url: &str,
     api_key: &str,
-    model: &str,
-    prompt: &str,
-    temperature: Option<f32>,
-    max_tokens: u32,
-    max_tokens_key: Option<String>,
-) -> Result<String, CrabError> {
+    request: &ChatRequest,
+) -> Result<Completion, CrabError> {
+    post_chat(client, base_url, api_key, &chat_body(request)).await
+}
+
+/// Builds the chat completions request body. A prefill is appended as a
+/// trailing assistant message for the model to continue.
+pub fn chat_body(request: &ChatRequest) -> serde_json::Value {
+    let mut messages = messages(request);
+    if let Some(prefill) = &request.prefill {
+        messages.push(Message {
+            role: Role::Assistant.as_str().to_string(),
+            content: serde_json::json!(prefill),
+        });
+    }
+
     let mut request_body = serde_json::json!({
-        "model": model,
-        "messages": [
-            Message {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-            }
-        ],
+        "model": request.model,
+        "messages": messages,
     });
 
-    if let Some(t) = temperature {
+    if let Some(t) = request.temperature {
         request_body["temperature"] = serde_json::json!(t);
     }
 
-    let key = max_tokens_key.unwrap_or_else(|| "max_tokens".to_string());
-    request_body[key] = serde_json::json!(max_tokens);
+    let key = request
+        .max_tokens_key
+        .clone()
+        .unwrap_or_else(|| "max_tokens".to_string());
+    request_body[key] = serde_json::json!(request.max_tokens);
+
+    if let Some(top) = request.logprobs {
+        request_body["logprobs"] = serde_json::json!(true);
+        if top > 0 {
+            request_body["top_logprobs"] = serde_json::json!(top);
+        }
+    }
+    omit_params(&mut request_body, &request.omit_params);
+    request_body
+}
+
+/// Flags a trailing assistant message with `"prefix": true`, which Mistral
+/// and DeepSeek require before they continue a prefilled answer.
+pub fn mark_prefix(request_body: &mut serde_json::Value) {
+    if let Some(last) = request_body["messages"]
+        .as_array_mut()
+        .and_then(|m| m.last_mut())
+    {
+        if last["role"] == Role::Assistant.as_str() {
+            last["prefix"] = serde_json::json!(true);
+        }
+    }
+}
 
+/// POST {base_url}/chat/completions with a prepared request body.
+pub async fn post_chat(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    request_body: &serde_json::Value,
+) -> Result<Completion, CrabError> {
+    post_chat_n(client, base_url, api_key, request_body)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| empty_response(client))
+}
+
+/// Like `post_chat`, but returns every choice of the response, for request
+/// bodies that ask for several answers with `n`.
+pub async fn post_chat_n(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    request_body: &serde_json::Value,
+) -> Result<Vec<Completion>, CrabError> {
     let url = format!("{base_url}/chat/completions");
     let resp = client
-        .post(&url)
-        .bearer_auth(api_key)
-        .json(&request_body)
-        .send()
+        .execute(client.post(&url).bearer_auth(api_key).json(request_body))
+        .await?;
+
+    let chat_resp: ChatResponse = resp.json().await?;
+    let completions = parse_chat_response(chat_resp);
+    if completions.is_empty() {
+        return Err(empty_response(client));
+    }
+    Ok(completions)
+}
+
+fn empty_response(client: &HttpClient) -> CrabError {
+    CrabError::ProviderError {
+        provider: client.provider().to_string(),
+        message: "Empty response from API".to_string(),
+    }
+}
+
+/// Sets the number of answers to generate on a chat completions body.
+pub fn set_n(request_body: &mut serde_json::Value, n: u32) {
+    request_body["n"] = serde_json::json!(n);
+}
+
+/// POST a fill-in-the-middle request to `url` and return the inserted code.
+pub async fn post_fim(
+    client: &HttpClient,
+    url: &str,
+    api_key: &str,
+    request: &FimRequest,
+) -> Result<Completion, CrabError> {
+    let mut request_body = serde_json::json!({
+        "model": request.model,
+        "prompt": request.prefix,
+        "suffix": request.suffix,
+        "max_tokens": request.max_tokens,
+    });
+    if let Some(t) = request.temperature {
+        request_body["temperature"] = serde_json::json!(t);
+    }
+
+    let resp = client
+        .execute(client.post(url).bearer_auth(api_key).json(&request_body))
         .await?;
 
-    let status = resp.status();
-    if !status.is_success() {
-        let body = resp.text().await.unwrap_or_default();
+    let fim_resp: FimResponse = resp.json().await?;
+    let output_tokens = fim_resp.usage.and_then(|u| u.completion_tokens);
+    let choice = fim_resp
+        .choices
+        .into_iter()
+        .next()
+        .ok_or_else(|| empty_response(client))?;
+    Ok(Completion {
+        text: choice
+            .text
+            .or_else(|| choice.message.and_then(|m| m.content))
+            .unwrap_or_default(),
+        finish_reason: choice.finish_reason.as_deref().map(FinishReason::parse),
+        output_tokens,
+        logprobs: None,
+        sources: Vec::new(),
+        id: None,
+        upstream_provider: None,
+    })
+}
+
+/// POST {base_url}/embeddings. `dimensions_key` names the parameter that
+/// carries the requested vector size (OpenAI `dimensions`, Mistral
+/// `output_dimension`); None means the API has no such option.
+pub async fn post_embeddings(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    request: &EmbedRequest,
+    dimensions_key: Option<&str>,
+) -> Result<Vec<Vec<f32>>, CrabError> {
+    let mut request_body = serde_json::json!({
+        "model": request.model,
+        "input": request.inputs,
+    });
+    if let Some(dimensions) = request.dimensions {
+        let key = dimensions_key.ok_or_else(|| {
+            CrabError::Unsupported(format!(
+                "{} embeddings do not support a dimensions option",
+                client.provider()
+            ))
+        })?;
+        request_body[key] = serde_json::json!(dimensions);
+    }
+
+    let url = format!("{base_url}/embeddings");
+    let resp = client
+        .execute(client.post(&url).bearer_auth(api_key).json(&request_body))
+        .await?;
+
+    let mut embeddings: EmbeddingsResponse = resp.json().await?;
+    if embeddings.data.len() != request.inputs.len() {
         return Err(CrabError::ProviderError {
-            provider: base_url.to_string(),
-            message: format!("HTTP {status}: {body}"),
+            provider: client.provider().to_string(),
+            message: format!(
+                "Expected {} embeddings, got {}",
+                request.inputs.len(),
+                embeddings.data.len()
+            ),
         });
     }
+    embeddings.data.sort_by_key(|e| e.index);
+    Ok(embeddings.data.into_iter().map(|e| e.embedding).collect())
+}
 
-    let chat_resp: ChatResponse = resp.json().await?;
-    chat_resp
-        .choices
+/// POST {base_url}/audio/transcriptions as a multipart upload. Uses
+/// `default_model` when the request does not name one.
+pub async fn post_transcription(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    request: &TranscriptionRequest,
+    default_model: &str,
+) -> Result<String, CrabError> {
+    let file = reqwest::multipart::Part::bytes(request.data.clone())
+        .file_name(request.file_name.clone())
+        .mime_str(&request.mime_type)?;
+    let form = reqwest::multipart::Form::new()
+        .text(
+            "model",
+            request.model.clone().unwrap_or_else(|| default_model.to_string()),
+        )
+        .text("response_format", "json")
+        .part("file", file);
+
+    let url = format!("{base_url}/audio/transcriptions");
+    let resp = client
+        .execute(client.post(&url).bearer_auth(api_key).multipart(form))
+        .await?;
+
+    let transcription: TranscriptionResponse = resp.json().await?;
+    Ok(transcription.text)
+}
+
+/// POST {base_url}/audio/speech and return the audio bytes. Uses
+/// `default_voice` when the request does not name one.
+pub async fn post_speech(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    request: &SpeechRequest,
+    default_voice: &str,
+) -> Result<Vec<u8>, CrabError> {
+    let mut request_body = serde_json::json!({
+        "model": request.model,
+        "input": request.input,
+        "voice": request.voice.as_deref().unwrap_or(default_voice),
+    });
+    if let Some(format) = &request.format {
+        request_body["response_format"] = serde_json::json!(format);
+    }
+    if let Some(speed) = request.speed {
+        request_body["speed"] = serde_json::json!(speed);
+    }
+
+    let url = format!("{base_url}/audio/speech");
+    let resp = client
+        .execute(client.post(&url).bearer_auth(api_key).json(&request_body))
+        .await?;
+    Ok(resp.bytes().await?.to_vec())
+}
+
+/// POST {base_url}/images/generations with a prepared request body.
+/// Images come back base64-encoded (`b64_json`) or as a `url`, which is
+/// downloaded through the same client, so it is recorded and replayed too.
+pub async fn post_image_generation(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    request_body: &serde_json::Value,
+) -> Result<Vec<GeneratedImage>, CrabError> {
+    let url = format!("{base_url}/images/generations");
+    let resp = client
+        .execute(client.post(&url).bearer_auth(api_key).json(request_body))
+        .await?;
+
+    let images: ImagesResponse = resp.json().await?;
+    let mut generated = Vec::with_capacity(images.data.len());
+    for entry in images.data {
+        let data = match (entry.b64_json, entry.url) {
+            (Some(b64), _) => base64::engine::general_purpose::STANDARD
+                .decode(b64)
+                .map_err(|e| CrabError::ProviderError {
+                    provider: client.provider().to_string(),
+                    message: format!("Invalid image data: {e}"),
+                })?,
+            (None, Some(url)) => {
+                let resp = client.execute(client.download(&url)).await?;
+                resp.bytes().await?.to_vec()
+            }
+            (None, None) => return Err(empty_response(client)),
+        };
+        generated.push(GeneratedImage(data));
+    }
+    Ok(generated)
+}
+
+/// Uploads the chat request bodies as a JSONL file to {base_url}/files and
+/// starts a batch job for them. The custom id of each line is its index.
+pub async fn post_batch(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    request_bodies: &[serde_json::Value],
+) -> Result<BatchStatus, CrabError> {
+    let mut jsonl = String::new();
+    for (index, body) in request_bodies.iter().enumerate() {
+        let line = serde_json::json!({
+            "custom_id": index.to_string(),
+            "method": "POST",
+            "url": "/v1/chat/completions",
+            "body": body,
+        });
+        jsonl.push_str(&line.to_string());
+        jsonl.push('\n');
+    }
+    let file = reqwest::multipart::Part::bytes(jsonl.into_bytes())
+        .file_name("batch.jsonl")
+        .mime_str("application/jsonl")?;
+    let form = reqwest::multipart::Form::new()
+        .text("purpose", "batch")
+        .part("file", file);
+
+    let url = format!("{base_url}/files");
+    let resp = client
+        .execute(client.post(&url).bearer_auth(api_key).multipart(form))
+        .await?;
+    let input_file: FileObject = resp.json().await?;
+
+    let request_body = serde_json::json!({
+        "input_file_id": input_file.id,
+        "endpoint": "/v1/chat/completions",
+        "completion_window": "24h",
+    });
+    let url = format!("{base_url}/batches");
+    let resp = client
+        .execute(client.post(&url).bearer_auth(api_key).json(&request_body))
+        .await?;
+    let batch: BatchObject = resp.json().await?;
+    Ok(batch.status())
+}
+
+/// GET {base_url}/batches/{id}.
+pub async fn get_batch(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    id: &str,
+) -> Result<BatchStatus, CrabError> {
+    Ok(get_batch_object(client, base_url, api_key, id).await?.status())
+}
+
+async fn get_batch_object(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    id: &str,
+) -> Result<BatchObject, CrabError> {
+    let url = format!("{base_url}/batches/{id}");
+    let resp = client
+        .execute(client.get(&url).bearer_auth(api_key))
+        .await?;
+    Ok(resp.json().await?)
+}
+
+/// Downloads the output and error files of a batch job and returns one
+/// result per line.
+pub async fn get_batch_results(
+    client: &HttpClient,
+    base_url: &str,
+    api_key: &str,
+    id: &str,
+) -> Result<Vec<BatchResult>, CrabError> {
+    let batch = get_batch_object(client, base_url, api_key, id).await?;
+    let mut results = Vec::new();
+    for file_id in [batch.output_file_id, batch.error_file_id].into_iter().flatten() {
+        let url = format!("{base_url}/files/{file_id}/content");
+        let resp = client
+            .execute(client.get(&url).bearer_auth(api_key))
+            .await?;
+        let content = resp.text().await?;
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            let entry: BatchOutputLine = serde_json::from_str(line)?;
+            results.push(BatchResult {
+                index: batch_index(client.provider(), &entry.custom_id)?,
+                outcome: batch_outcome(entry),
+            });
+        }
+    }
+    Ok(results)
+}
+
+fn batch_outcome(entry: BatchOutputLine) -> Result<Completion, String> {
+    if let Some(error) = entry.error {
+        return Err(error.message);
+    }
+    let response = entry
+        .response
+        .ok_or_else(|| "No response in batch output".to_string())?;
+    if response.status_code != 200 {
+        return Err(response.body["error"]["message"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("HTTP {}", response.status_code)));
+    }
+    let chat_resp: ChatResponse =
+        serde_json::from_value(response.body).map_err(|e| e.to_string())?;
+    parse_chat_response(chat_resp)
         .into_iter()
         .next()
-        .map(|c| c.message.content)
-        .ok_or_else(|| CrabError::ProviderError {
-            provider: base_url.to_string(),
-            message: "Empty response from API".to_string(),
+        .ok_or_else(|| "Empty response from API".to_string())
+}
+
+/// Converts request messages into the OpenAI chat message format.
+fn messages(request: &ChatRequest) -> Vec<Message> {
+    request
+        .messages
+        .iter()
+        .map(|m| Message {
+            role: m.role.as_str().to_string(),
+            content: message_content(m),
+        })
+        .collect()
+}
+
+/// The message text, or content parts with the attachments first: images
+/// as `image_url` parts and PDFs as `file` parts, both as data URLs.
+fn message_content(message: &ChatMessage) -> serde_json::Value {
+    if message.attachments.is_empty() {
+        return serde_json::json!(message.content);
+    }
+    let mut parts: Vec<serde_json::Value> = message
+        .attachments
+        .iter()
+        .map(|a| {
+            if a.is_pdf() {
+                serde_json::json!({
+                    "type": "file",
+                    "file": { "filename": a.file_name, "file_data": a.data_url() },
+                })
+            } else {
+                serde_json::json!({
+                    "type": "image_url",
+                    "image_url": { "url": a.data_url() },
+                })
+            }
+        })
+        .collect();
+    if !message.content.is_empty() {
+        parts.push(serde_json::json!({ "type": "text", "text": message.content }));
+    }
+    serde_json::Value::Array(parts)
+}
+
+/// Extracts the choices of a chat completion response. The reported
+/// output token count covers all choices, so it is only kept for one.
+fn parse_chat_response(resp: ChatResponse) -> Vec<Completion> {
+    let output_tokens = resp
+        .usage
+        .and_then(|u| u.completion_tokens)
+        .filter(|_| resp.choices.len() == 1);
+    resp.choices
+        .into_iter()
+        .map(|choice| {
+            let mut completion = Completion {
+                text: choice.message.content.unwrap_or_default(),
+                finish_reason: choice.finish_reason.as_deref().map(FinishReason::parse),
+                output_tokens,
+                logprobs: choice
+                    .logprobs
+                    .and_then(|l| l.content)
+                    .map(|entries| entries.into_iter().map(token_logprob).collect()),
+                sources: Vec::new(),
+                id: resp.id.clone(),
+                upstream_provider: resp.provider.clone(),
+            };
+            completion.add_sources(
+                choice
+                    .message
+                    .annotations
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|a| a.url_citation)
+                    .map(|c| Source {
+                        url: c.url,
+                        title: c.title,
+                    }),
+            );
+            completion
         })
+        .collect()
+}
+
+fn token_logprob(entry: LogprobEntry) -> TokenLogprob {
+    TokenLogprob {
+        token: entry.token,
+        logprob: entry.logprob,
+        top_logprobs: entry
+            .top_logprobs
+            .into_iter()
+            .map(|t| TopLogprob {
+                token: t.token,
+                logprob: t.logprob,
+            })
+            .collect(),
+    }
 }
 
 /// GET {base_url}/models. Returns sorted model info.
 pub async fn list_models_api(
-    client: &Client,
+    client: &HttpClient,
     base_url: &str,
     api_key: &str,
 ) -> Result<Vec<ModelInfo>, CrabError> {
     let url = format!("{base_url}/models");
-    let resp = client.get(&url).bearer_auth(api_key).send().await?;
-
-    let status = resp.status();
-    if !status.is_success() {
-        let body = resp.text().await.unwrap_or_default();
-        return Err(CrabError::ProviderError {
-            provider: base_url.to_string(),
-            message: format!("HTTP {status}: {body}"),
-        });
-    }
+    let resp = client
+        .execute(client.get(&url).bearer_auth(api_key))
+        .await?;
 
     let models_resp: ModelsResponse = resp.json().await?;
     let mut models: Vec<ModelInfo> = models_resp
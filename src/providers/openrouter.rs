@@ -1,31 +1,72 @@
 use async_trait::async_trait;
-use reqwest::Client;
 
 use super::openai_compat;
+use super::http::HttpClient;
 use super::r#trait::Provider;
+use crate::api_key;
+use crate::config::{Config, OpenRouterAdvancedConfig};
 use crate::error::CrabError;
-use crate::types::ModelInfo;
+use crate::types::{Capabilities, ChatRequest, Completion, DEFAULT_TEMPERATURE_RANGE, ModelInfo};
 
-/// OpenRouter aggregator. OpenAI-compatible API.
+/// OpenRouter aggregator. OpenAI-compatible API, plus routing preferences
+/// and fallback models from `advanced.openrouter`.
 pub struct OpenRouterProvider {
-    client: Client,
-    api_key: Option<String>,
+    client: HttpClient,
+    base_url: String,
+    api_keys: api_key::Keys,
+    routing: OpenRouterAdvancedConfig,
 }
 
 impl OpenRouterProvider {
     const BASE_URL: &'static str = "https://openrouter.ai/api/v1";
 
-    /// Creates a new provider instance with a custom environment variable name.
-    pub fn new_with_env(env_var: &str) -> Result<Self, CrabError> {
+    /// Creates a new provider instance from the application config.
+    pub fn new(config: &Config) -> Result<Self, CrabError> {
+        let routing = config
+            .advanced
+            .as_ref()
+            .and_then(|a| a.openrouter.clone())
+            .unwrap_or_default();
+        routing.validate()?;
         Ok(Self {
-            client: Client::new(),
-            api_key: std::env::var(env_var).ok(),
+            client: HttpClient::new("openrouter", config)?,
+            base_url: config.provider_base_url("openrouter", Self::BASE_URL),
+            api_keys: config.api_key_sources("openrouter")?,
+            routing,
         })
     }
 
+    /// Adds the routing preferences (`provider`), fallback `models` and
+    /// `transforms` to a chat completions body.
+    fn apply_routing(&self, request_body: &mut serde_json::Value) {
+        let routing = &self.routing;
+        let mut preferences = serde_json::Map::new();
+        if let Some(order) = &routing.order {
+            preferences.insert("order".to_string(), serde_json::json!(order));
+        }
+        if let Some(allow) = routing.allow_fallbacks {
+            preferences.insert("allow_fallbacks".to_string(), serde_json::json!(allow));
+        }
+        if let Some(policy) = &routing.data_collection {
+            preferences.insert("data_collection".to_string(), serde_json::json!(policy));
+        }
+        if let Some(quantizations) = &routing.quantizations {
+            preferences.insert("quantizations".to_string(), serde_json::json!(quantizations));
+        }
+        if !preferences.is_empty() {
+            request_body["provider"] = serde_json::Value::Object(preferences);
+        }
+        if let Some(models) = &routing.models {
+            request_body["models"] = serde_json::json!(models);
+        }
+        if let Some(transforms) = &routing.transforms {
+            request_body["transforms"] = serde_json::json!(transforms);
+        }
+    }
+
     fn require_key(&self) -> Result<&str, CrabError> {
-        self.api_key
-            .as_deref()
+        self.api_keys
+            .active()?
             .ok_or_else(|| CrabError::MissingApiKey("openrouter".to_string()))
     }
 
@@ -50,18 +91,13 @@ impl OpenRouterProvider {
         let api_key = self.require_key()?;
         let resp = self
             .client
-            .get(format!("{}/models", Self::BASE_URL))
-            .bearer_auth(api_key)
-            .send()
+            .execute(
+                self.client
+                    .get(&format!("{}/models", &self.base_url))
+                    .bearer_auth(api_key),
+            )
             .await?;
 
-        if !resp.status().is_success() {
-            return Err(CrabError::ProviderError {
-                provider: "openrouter".to_string(),
-                message: format!("API error: {}", resp.status()),
-            });
-        }
-
         let models_list: ModelsList = resp.json().await.map_err(|e| CrabError::ProviderError {
             provider: "openrouter".to_string(),
             message: format!("Failed to parse models list: {}", e),
@@ -73,45 +109,74 @@ impl OpenRouterProvider {
 
 #[async_trait]
 impl Provider for OpenRouterProvider {
-    async fn send(
-        &self,
-        model: &str,
-        prompt: &str,
-        temperature: Option<f32>,
-        max_tokens: u32,
-        max_tokens_key: Option<String>,
-    ) -> Result<String, CrabError> {
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
         let api_key = self.require_key()?;
-        openai_compat::send_chat_request(
-            &self.client,
-            Self::BASE_URL,
-            api_key,
-            model,
-            prompt,
-            temperature,
-            max_tokens,
-            max_tokens_key,
-        )
-        .await
+        // The ":online" variant of a model runs OpenRouter's web search plugin.
+        let mut request = request.clone();
+        if request.web_search && !request.model.ends_with(":online") {
+            request.model.push_str(":online");
+        }
+        let mut request_body = openai_compat::chat_body(&request);
+        self.apply_routing(&mut request_body);
+        openai_compat::post_chat(&self.client, &self.base_url, api_key, &request_body).await
     }
 
     async fn list_models(&self) -> Result<Vec<ModelInfo>, CrabError> {
-        match self.list_models_api().await {
-            Ok(models) => Ok(models
-                .into_iter()
-                .map(|m| {
-                    let mut info = ModelInfo::new(&m.id);
-                    info.max_output_tokens = m.top_provider.max_completion_tokens;
-                    info
-                })
-                .collect()),
-            Err(_) => Ok(Self::static_models()),
+        if self.api_keys.active()?.is_none() {
+            return Ok(Self::static_models());
         }
+        Ok(self
+            .list_models_api()
+            .await?
+            .into_iter()
+            .map(|m| {
+                let mut info = ModelInfo::new(&m.id);
+                info.max_output_tokens = m.top_provider.max_completion_tokens;
+                info.supports_logprobs = m
+                    .supported_parameters
+                    .map(|params| params.iter().any(|p| p == "logprobs"));
+                info.supports_vision = m
+                    .architecture
+                    .and_then(|a| a.input_modalities)
+                    .map(|modalities| modalities.iter().any(|m| m == "image"));
+                info
+            })
+            .collect())
     }
 
     fn name(&self) -> &str {
         "openrouter"
     }
+
+    fn capabilities(&self) -> Capabilities {
+        // Options are passed through to the upstream provider, which may
+        // ignore them; the model list knows better for images and logprobs.
+        // PDFs are parsed by OpenRouter itself for models without native
+        // support.
+        Capabilities {
+            system_role: true,
+            streaming: true,
+            tools: true,
+            vision: true,
+            pdf: true,
+            json_schema: true,
+            reasoning: true,
+            prefill: true,
+            logprobs: true,
+            web_search: true,
+            temperature: Some(DEFAULT_TEMPERATURE_RANGE),
+            max_tokens_param: None,
+        }
+    }
+
+    fn model_capabilities(&self, model: &str) -> Capabilities {
+        Capabilities {
+            reasoning: ["/o1", "/o3", "/o4", "-r1", ":thinking"]
+                .iter()
+                .any(|name| model.contains(name)),
+            ..self.capabilities()
+        }
+    }
 }
 
 // Add the structs needed for deserialization of the model list
@@ -120,6 +185,13 @@ struct OpenRouterModel {
     id: String,
     #[serde(rename = "top_provider")]
     top_provider: TopProvider,
+    supported_parameters: Option<Vec<String>>,
+    architecture: Option<Architecture>,
+}
+
+#[derive(serde::Deserialize)]
+struct Architecture {
+    input_modalities: Option<Vec<String>>,
 }
 
 #[derive(serde::Deserialize)]
@@ -0,0 +1,344 @@
+//! External-executable provider plugins.
+//!
+//! A plugin is any executable named `crabai-provider-<name>` on PATH, or
+//! declared under `[advanced.plugins]` in the config. It is used as provider
+//! `<name>`; built-in providers take precedence over plugins of the same
+//! name.
+//!
+//! Protocol (JSON over stdio, one process per call): crabai writes one
+//! JSON request line to the plugin's STDIN and closes it:
+//! - `{"protocol": 1, "method": "send", "params": {"model", "messages":
+//!   [{"role", "content"}], "temperature", "max_tokens"}}`
+//! - `{"protocol": 1, "method": "list_models"}`
+//!
+//! The plugin answers with JSON lines on STDOUT:
+//! - `{"type": "chunk", "text"}`: part of the answer, in order (streaming)
+//! - `{"type": "result", "text", "finish_reason", "output_tokens"}` for
+//!   `send`; all fields optional, `text` defaults to the chunks so far
+//! - `{"type": "result", "models": [{"id", "max_output_tokens"}]}` for
+//!   `list_models`
+//! - `{"type": "error", "message", "status", "code"}`: `status` and `code`
+//!   are optional; with an HTTP status the error is classified like a
+//!   provider HTTP error (401 auth, 429 rate limit, ...)
+//!
+//! The first `result` or `error` line ends the call. STDERR is passed
+//! through to the user.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+use super::errors;
+use super::omit_params;
+use super::r#trait::Provider;
+use crate::config::Config;
+use crate::error::CrabError;
+use crate::types::{ChatRequest, Completion, FinishReason, ModelInfo};
+
+/// Executable name prefix of plugins found on PATH.
+const PREFIX: &str = "crabai-provider-";
+
+/// Version of the stdio protocol sent with every request.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// A provider implemented by an external executable.
+pub struct PluginProvider {
+    name: String,
+    path: PathBuf,
+}
+
+/// One line of plugin output.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Output {
+    Chunk {
+        text: String,
+    },
+    Result(Reply),
+    Error {
+        message: String,
+        status: Option<u16>,
+        code: Option<String>,
+    },
+}
+
+/// The final `result` line of a call.
+#[derive(Deserialize)]
+struct Reply {
+    text: Option<String>,
+    finish_reason: Option<String>,
+    output_tokens: Option<u32>,
+    #[serde(default)]
+    models: Vec<PluginModel>,
+}
+
+#[derive(Deserialize)]
+struct PluginModel {
+    id: String,
+    max_output_tokens: Option<u32>,
+}
+
+/// Returns the executable of plugin `name`: the one declared in the config,
+/// otherwise `crabai-provider-<name>` on PATH.
+pub fn find(name: &str, config: &Config) -> Option<PathBuf> {
+    config
+        .plugin_paths()
+        .remove(name)
+        .or_else(|| path_plugins().remove(name))
+}
+
+/// Names of all plugins declared in the config or found on PATH, sorted.
+pub fn names(config: &Config) -> Vec<String> {
+    let mut plugins = path_plugins();
+    plugins.extend(config.plugin_paths());
+    plugins.into_keys().collect()
+}
+
+/// Plugins on PATH by name. The first directory providing a name wins.
+fn path_plugins() -> BTreeMap<String, PathBuf> {
+    let mut plugins = BTreeMap::new();
+    let Some(path) = std::env::var_os("PATH") else {
+        return plugins;
+    };
+    for dir in std::env::split_paths(&path) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name
+                .to_str()
+                .and_then(|f| f.strip_prefix(PREFIX))
+                .map(|n| n.trim_end_matches(std::env::consts::EXE_SUFFIX))
+            else {
+                continue;
+            };
+            if !name.is_empty() && is_executable(&entry.path()) {
+                plugins
+                    .entry(name.to_string())
+                    .or_insert_with(|| entry.path());
+            }
+        }
+    }
+    plugins
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+impl PluginProvider {
+    pub fn new(name: &str, path: PathBuf) -> Self {
+        Self {
+            name: name.to_string(),
+            path,
+        }
+    }
+
+    /// Runs the plugin with one request. Returns the text of the chunks and
+    /// the final result line.
+    async fn call(&self, request: serde_json::Value) -> Result<(String, Reply), CrabError> {
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| self.error(format!("Cannot run {}: {e}", self.path.display())))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            let mut line = serde_json::to_vec(&request)?;
+            line.push(b'\n');
+            // A plugin may exit without reading its input (e.g. on a usage
+            // error); its output explains why.
+            let _ = stdin.write_all(&line).await;
+        }
+
+        let mut chunks = String::new();
+        if let Some(stdout) = child.stdout.take() {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Some(line) = lines.next_line().await? {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let output: Output = serde_json::from_str(&line)
+                    .map_err(|e| self.error(format!("Invalid plugin output ({e}): {line}")))?;
+                match output {
+                    Output::Chunk { text } => chunks.push_str(&text),
+                    Output::Error {
+                        message,
+                        status,
+                        code,
+                    } => return Err(self.plugin_error(message, status, code)),
+                    Output::Result(reply) => return Ok((chunks, reply)),
+                }
+            }
+        }
+
+        let status = child.wait().await?;
+        Err(self.error(format!("Plugin exited without a result ({status})")))
+    }
+
+    fn error(&self, message: String) -> CrabError {
+        CrabError::ProviderError {
+            provider: self.name.clone(),
+            message,
+        }
+    }
+
+    /// Converts a reported error. With an HTTP status, it is classified
+    /// like a provider's HTTP error response.
+    fn plugin_error(
+        &self,
+        message: String,
+        status: Option<u16>,
+        code: Option<String>,
+    ) -> CrabError {
+        match status.and_then(|s| StatusCode::from_u16(s).ok()) {
+            Some(status) => {
+                let body = serde_json::json!({ "error": { "message": message, "code": code } });
+                errors::from_response(&self.name, status, &HeaderMap::new(), &body.to_string())
+            }
+            None => self.error(message),
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for PluginProvider {
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
+        let mut params = serde_json::json!({
+            "model": request.model,
+            "messages": request
+                .messages
+                .iter()
+                .map(|m| serde_json::json!({ "role": m.role.as_str(), "content": m.content }))
+                .collect::<Vec<_>>(),
+            "temperature": request.temperature,
+            "max_tokens": request.max_tokens,
+        });
+        omit_params(&mut params, &request.omit_params);
+        let (chunks, reply) = self
+            .call(serde_json::json!({
+                "protocol": PROTOCOL_VERSION,
+                "method": "send",
+                "params": params,
+            }))
+            .await?;
+        Ok(Completion {
+            text: reply.text.unwrap_or(chunks),
+            finish_reason: reply.finish_reason.as_deref().map(FinishReason::parse),
+            output_tokens: reply.output_tokens,
+            logprobs: None,
+            sources: Vec::new(),
+            id: None,
+            upstream_provider: None,
+        })
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, CrabError> {
+        let (_, reply) = self
+            .call(serde_json::json!({
+                "protocol": PROTOCOL_VERSION,
+                "method": "list_models",
+            }))
+            .await?;
+        Ok(reply
+            .models
+            .into_iter()
+            .map(|m| {
+                let mut info = ModelInfo::new(&m.id);
+                info.max_output_tokens = m.max_output_tokens;
+                info
+            })
+            .collect())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Output {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn parses_chunk_and_result_lines() {
+        assert!(matches!(
+            parse(r#"{"type": "chunk", "text": "Hel"}"#),
+            Output::Chunk { text } if text == "Hel"
+        ));
+        let Output::Result(reply) = parse(
+            r#"{"type": "result", "text": "Hello", "finish_reason": "stop", "output_tokens": 2}"#,
+        ) else {
+            panic!("expected a result line");
+        };
+        assert_eq!(reply.text.as_deref(), Some("Hello"));
+        assert_eq!(reply.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(reply.output_tokens, Some(2));
+        assert!(reply.models.is_empty());
+    }
+
+    #[test]
+    fn parses_model_list() {
+        let Output::Result(reply) = parse(concat!(
+            r#"{"type": "result", "models": "#,
+            r#"[{"id": "small"}, {"id": "large", "max_output_tokens": 8192}]}"#,
+        )) else {
+            panic!("expected a result line");
+        };
+        let models: Vec<_> = reply
+            .models
+            .iter()
+            .map(|m| (m.id.as_str(), m.max_output_tokens))
+            .collect();
+        assert_eq!(models, [("small", None), ("large", Some(8192))]);
+    }
+
+    #[test]
+    fn rejects_unknown_line_type() {
+        assert!(serde_json::from_str::<Output>(r#"{"type": "progress"}"#).is_err());
+    }
+
+    #[test]
+    fn classifies_reported_errors_by_status() {
+        let plugin = PluginProvider::new("local", PathBuf::from("crabai-provider-local"));
+        let Output::Error {
+            message,
+            status,
+            code,
+        } = parse(r#"{"type": "error", "message": "bad key", "status": 401}"#)
+        else {
+            panic!("expected an error line");
+        };
+        assert!(matches!(
+            plugin.plugin_error(message, status, code),
+            CrabError::Auth(_)
+        ));
+        assert!(matches!(
+            plugin.plugin_error("model crashed".to_string(), None, None),
+            CrabError::ProviderError { message, .. } if message == "model crashed"
+        ));
+    }
+}
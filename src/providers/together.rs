@@ -1,31 +1,38 @@
 use async_trait::async_trait;
-use reqwest::Client;
 
 use super::openai_compat;
+use super::http::HttpClient;
 use super::r#trait::Provider;
+use crate::api_key;
+use crate::config::Config;
 use crate::error::CrabError;
-use crate::types::ModelInfo;
+use crate::types::{
+    Capabilities, ChatRequest, Completion, EmbedRequest, GeneratedImage, ImageOptions, ImageRequest,
+    ModelInfo, SpeechRequest,
+};
 
 /// Together AI inference API. OpenAI-compatible.
 pub struct TogetherProvider {
-    client: Client,
-    api_key: Option<String>,
+    client: HttpClient,
+    base_url: String,
+    api_keys: api_key::Keys,
 }
 
 impl TogetherProvider {
     const BASE_URL: &'static str = "https://api.together.xyz/v1";
 
-    /// Creates a new provider instance with a custom environment variable name.
-    pub fn new_with_env(env_var: &str) -> Result<Self, CrabError> {
+    /// Creates a new provider instance from the application config.
+    pub fn new(config: &Config) -> Result<Self, CrabError> {
         Ok(Self {
-            client: Client::new(),
-            api_key: std::env::var(env_var).ok(),
+            client: HttpClient::new("together", config)?,
+            base_url: config.provider_base_url("together", Self::BASE_URL),
+            api_keys: config.api_key_sources("together")?,
         })
     }
 
     fn require_key(&self) -> Result<&str, CrabError> {
-        self.api_key
-            .as_deref()
+        self.api_keys
+            .active()?
             .ok_or_else(|| CrabError::MissingApiKey("together".to_string()))
     }
 
@@ -44,40 +51,99 @@ impl TogetherProvider {
 
 #[async_trait]
 impl Provider for TogetherProvider {
-    async fn send(
-        &self,
-        model: &str,
-        prompt: &str,
-        temperature: Option<f32>,
-        max_tokens: u32,
-        max_tokens_key: Option<String>,
-    ) -> Result<String, CrabError> {
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError> {
         openai_compat::send_chat_request(
             &self.client,
-            Self::BASE_URL,
+            &self.base_url,
             self.require_key()?,
-            model,
-            prompt,
-            temperature,
-            max_tokens,
-            max_tokens_key,
+            request,
         )
         .await
     }
 
+    async fn send_n(&self, request: &ChatRequest, n: u32) -> Result<Vec<Completion>, CrabError> {
+        let mut body = openai_compat::chat_body(request);
+        openai_compat::set_n(&mut body, n);
+        openai_compat::post_chat_n(&self.client, &self.base_url, self.require_key()?, &body).await
+    }
+
+    async fn embed(&self, request: &EmbedRequest) -> Result<Vec<Vec<f32>>, CrabError> {
+        let api_key = self.require_key()?;
+        openai_compat::post_embeddings(&self.client, &self.base_url, api_key, request, None)
+            .await
+    }
+
+    async fn generate_images(
+        &self,
+        request: &ImageRequest,
+    ) -> Result<Vec<GeneratedImage>, CrabError> {
+        let mut body = serde_json::json!({
+            "model": request.model,
+            "prompt": request.prompt,
+            "n": request.count,
+            "response_format": "base64",
+        });
+        // Validated as WIDTHxHEIGHT before the request is built.
+        if let Some((width, height)) = request.size.as_deref().and_then(|s| s.split_once('x')) {
+            body["width"] = serde_json::json!(width.parse::<u32>().unwrap_or_default());
+            body["height"] = serde_json::json!(height.parse::<u32>().unwrap_or_default());
+        }
+        let api_key = self.require_key()?;
+        openai_compat::post_image_generation(&self.client, &self.base_url, api_key, &body).await
+    }
+
+    async fn speak(&self, request: &SpeechRequest) -> Result<Vec<u8>, CrabError> {
+        let api_key = self.require_key()?;
+        openai_compat::post_speech(
+            &self.client,
+            &self.base_url,
+            api_key,
+            request,
+            "laidback woman",
+        )
+        .await
+    }
 
     async fn list_models(&self) -> Result<Vec<ModelInfo>, CrabError> {
         let api_key = match self.require_key() {
             Ok(k) => k,
             Err(_) => return Ok(Self::static_models()),
         };
-        match openai_compat::list_models_api(&self.client, Self::BASE_URL, api_key).await {
-            Ok(models) => Ok(models),
-            Err(_) => Ok(Self::static_models()),
-        }
+        openai_compat::list_models_api(&self.client, &self.base_url, api_key).await
     }
 
     fn name(&self) -> &str {
         "together"
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            system_role: true,
+            streaming: true,
+            tools: true,
+            vision: true,
+            json_schema: true,
+            reasoning: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn model_capabilities(&self, model: &str) -> Capabilities {
+        let model = model.to_lowercase();
+        Capabilities {
+            vision: model.contains("vision") || model.contains("llama-4") || model.contains("-vl"),
+            reasoning: model.contains("deepseek-r1") || model.contains("qwq"),
+            ..self.capabilities()
+        }
+    }
+
+    fn image_options(&self, model: &str) -> Option<ImageOptions> {
+        let lower = model.to_lowercase();
+        (lower.contains("flux") || lower.contains("stable-diffusion")).then_some(ImageOptions {
+            sizes: &[],
+            any_size: true,
+            qualities: &[],
+            max_count: 4,
+        })
+    }
 }
@@ -1,6 +1,9 @@
 use async_trait::async_trait;
 use crate::error::CrabError;
-use crate::types::ModelInfo;
+use crate::types::{
+    BatchResult, BatchStatus, Capabilities, ChatRequest, Completion, EmbedRequest, FimRequest,
+    GeneratedImage, ImageOptions, ImageRequest, ModelInfo, SpeechRequest, TranscriptionRequest,
+};
 
 /// Common interface for all LLM provider implementations.
 ///
@@ -11,22 +14,115 @@ use crate::types::ModelInfo;
 /// Providers must be Send + Sync to support async/tokio runtime.
 #[async_trait]
 pub trait Provider: Send + Sync {
-    /// Sends a prompt to the model and returns the text response.
+    /// Sends the request messages to the model and returns its answer.
     ///
     /// This method performs the core LLM inference operation. The prompt
-    /// is sent as a single user message (no conversation history). The
-    /// temperature and max_tokens parameters control response generation.
+    /// is usually a single user message; continuation of truncated answers
+    /// adds the partial answer and a follow-up turn. The temperature and
+    /// max_tokens fields control response generation, and any parameter
+    /// listed in omit_params is left out of the request body.
     ///
     /// Returns the raw text content from the model without additional
-    /// formatting or metadata.
-    async fn send(
+    /// formatting, along with the finish reason and token usage when the
+    /// provider reports them.
+    async fn send(&self, request: &ChatRequest) -> Result<Completion, CrabError>;
+
+    /// Returns `n` independent answers to the same request.
+    ///
+    /// Providers whose API can generate several answers in one call
+    /// (OpenAI `n`, Gemini `candidateCount`) override this. The default
+    /// sends `n` requests in parallel.
+    async fn send_n(&self, request: &ChatRequest, n: u32) -> Result<Vec<Completion>, CrabError> {
+        futures::future::try_join_all((0..n).map(|_| self.send(request))).await
+    }
+
+    /// Fill-in-the-middle code completion: returns only the code to insert
+    /// between the request's prefix and suffix.
+    ///
+    /// Only providers with a dedicated FIM endpoint implement this; the
+    /// default fails with an Unsupported error.
+    async fn complete_fim(&self, _request: &FimRequest) -> Result<Completion, CrabError> {
+        Err(CrabError::Unsupported(format!(
+            "{} does not support fill-in-the-middle completion",
+            self.name()
+        )))
+    }
+
+    /// Returns one embedding vector per input, in input order.
+    ///
+    /// Only providers with an embeddings endpoint implement this; the
+    /// default fails with an Unsupported error.
+    async fn embed(&self, _request: &EmbedRequest) -> Result<Vec<Vec<f32>>, CrabError> {
+        Err(CrabError::Unsupported(format!(
+            "{} does not provide embeddings",
+            self.name()
+        )))
+    }
+
+    /// Transcribes an audio file and returns the transcript text.
+    ///
+    /// Only providers that accept audio implement this; the default fails
+    /// with an Unsupported error.
+    async fn transcribe(&self, _request: &TranscriptionRequest) -> Result<String, CrabError> {
+        Err(CrabError::Unsupported(format!(
+            "{} does not support audio transcription",
+            self.name()
+        )))
+    }
+
+    /// Reads text aloud and returns the encoded audio.
+    ///
+    /// Only providers with a text-to-speech endpoint implement this; the
+    /// default fails with an Unsupported error.
+    async fn speak(&self, _request: &SpeechRequest) -> Result<Vec<u8>, CrabError> {
+        Err(CrabError::Unsupported(format!(
+            "{} does not support text-to-speech",
+            self.name()
+        )))
+    }
+
+    /// Generates images from a prompt.
+    ///
+    /// Only providers with an image generation API implement this; the
+    /// default fails with an Unsupported error.
+    async fn generate_images(
         &self,
-        model: &str,
-        prompt: &str,
-        temperature: Option<f32>,
-        max_tokens: u32,
-        max_tokens_key: Option<String>,
-    ) -> Result<String, CrabError>;
+        _request: &ImageRequest,
+    ) -> Result<Vec<GeneratedImage>, CrabError> {
+        Err(CrabError::Unsupported(format!(
+            "{} does not support image generation",
+            self.name()
+        )))
+    }
+
+    /// Submits the requests as one asynchronous batch job and returns its
+    /// initial status. Each request is identified in the results by its
+    /// index in `requests`.
+    ///
+    /// Only providers with a batch API implement this; the default and the
+    /// other batch methods fail with an Unsupported error.
+    async fn submit_batch(&self, _requests: &[ChatRequest]) -> Result<BatchStatus, CrabError> {
+        Err(CrabError::Unsupported(format!(
+            "{} does not support batch jobs",
+            self.name()
+        )))
+    }
+
+    /// Returns the current status of a batch job.
+    async fn batch_status(&self, _id: &str) -> Result<BatchStatus, CrabError> {
+        Err(CrabError::Unsupported(format!(
+            "{} does not support batch jobs",
+            self.name()
+        )))
+    }
+
+    /// Downloads the results of a finished batch job, in no particular order.
+    async fn fetch_batch(&self, _id: &str) -> Result<Vec<BatchResult>, CrabError> {
+        Err(CrabError::Unsupported(format!(
+            "{} does not support batch jobs",
+            self.name()
+        )))
+    }
 
     /// Returns a list of available model identifiers and their capabilities.
     async fn list_models(&self) -> Result<Vec<ModelInfo>, CrabError>;
@@ -35,10 +131,23 @@ pub trait Provider: Send + Sync {
     /// Examples: "openai", "anthropic", "google", "mistral"
     fn name(&self) -> &str;
 
-    /// Sanitizes parameters based on model-specific constraints.
-    /// Returns (final_temperature, final_max_tokens).
-    /// Returning None for temperature signals the provider to omit the parameter from the request.
-    fn sanitize_params(&self, _model: &str, temperature: f32, max_tokens: u32) -> (Option<f32>, u32) {
-        (Some(temperature), max_tokens)
+    /// What the provider's API supports for at least some of its models.
+    /// The default describes plain chat without any optional feature.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// What the given model supports. Checked against the requested
+    /// options before sending, and used to fit the temperature into the
+    /// model's range. Defaults to the provider's capabilities.
+    fn model_capabilities(&self, _model: &str) -> Capabilities {
+        self.capabilities()
+    }
+
+    /// Sizes, qualities and counts an image model accepts, used to validate
+    /// requests before sending them. None if the model is not known to
+    /// generate images.
+    fn image_options(&self, _model: &str) -> Option<ImageOptions> {
+        None
     }
 }
@@ -0,0 +1,431 @@
+//! Per-provider, per-key quota state parsed from rate-limit response headers,
+//! stored as JSON at ~/.config/crabai/rate_limits.json. Used to wait for a
+//! quota reset instead of running into HTTP 429, and to report the remaining
+//! quota in --verbose mode.
+//!
+//! The file is read once per process into memory shared by all provider
+//! clients, so concurrent requests do not race on it, and the entries this
+//! process changed are written back once at exit (see `save`).
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::CrabError;
+
+/// Header names for each quota field, in lookup order. Covers the OpenAI
+/// convention (also used by Groq, Together, DeepSeek, OpenRouter), Anthropic,
+/// and Mistral's per-minute token headers.
+const REMAINING_REQUESTS: &[&str] = &[
+    "x-ratelimit-remaining-requests",
+    "anthropic-ratelimit-requests-remaining",
+    "x-ratelimit-remaining",
+];
+const LIMIT_REQUESTS: &[&str] = &[
+    "x-ratelimit-limit-requests",
+    "anthropic-ratelimit-requests-limit",
+    "x-ratelimit-limit",
+];
+const RESET_REQUESTS: &[&str] = &[
+    "x-ratelimit-reset-requests",
+    "anthropic-ratelimit-requests-reset",
+    "x-ratelimit-reset",
+];
+const REMAINING_TOKENS: &[&str] = &[
+    "x-ratelimit-remaining-tokens",
+    "anthropic-ratelimit-tokens-remaining",
+    "x-ratelimit-remaining-tokens-minute",
+];
+const LIMIT_TOKENS: &[&str] = &[
+    "x-ratelimit-limit-tokens",
+    "anthropic-ratelimit-tokens-limit",
+    "x-ratelimit-limit-tokens-minute",
+];
+const RESET_TOKENS: &[&str] = &["x-ratelimit-reset-tokens", "anthropic-ratelimit-tokens-reset"];
+
+/// Latest known quota for one provider key. Reset times are Unix
+/// timestamps in milliseconds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Quota {
+    pub remaining_requests: Option<u64>,
+    pub limit_requests: Option<u64>,
+    pub requests_reset_ms: Option<u64>,
+    pub remaining_tokens: Option<u64>,
+    pub limit_tokens: Option<u64>,
+    pub tokens_reset_ms: Option<u64>,
+    /// Unix timestamp (milliseconds) when this state was observed.
+    pub updated_ms: u64,
+}
+
+impl Quota {
+    /// Parses rate-limit headers from a provider response. Returns None if
+    /// the response carries none of the known headers.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let now = now_ms();
+        let quota = Self {
+            remaining_requests: header_u64(headers, REMAINING_REQUESTS),
+            limit_requests: header_u64(headers, LIMIT_REQUESTS),
+            requests_reset_ms: header_reset(headers, RESET_REQUESTS, now),
+            remaining_tokens: header_u64(headers, REMAINING_TOKENS),
+            limit_tokens: header_u64(headers, LIMIT_TOKENS),
+            tokens_reset_ms: header_reset(headers, RESET_TOKENS, now),
+            updated_ms: now,
+        };
+        let empty = Self {
+            updated_ms: now,
+            ..Self::default()
+        };
+        (quota != empty).then_some(quota)
+    }
+
+    /// Quota state implied by an HTTP 429 with a Retry-After header: no
+    /// requests left until the given number of seconds has passed.
+    pub fn exhausted_for(retry_after_secs: u64) -> Self {
+        let now = now_ms();
+        Self {
+            remaining_requests: Some(0),
+            requests_reset_ms: Some(now + retry_after_secs * 1000),
+            updated_ms: now,
+            ..Self::default()
+        }
+    }
+
+    /// How long to wait before the next request, if a quota is exhausted
+    /// and its reset time is still in the future.
+    pub fn wait_time(&self) -> Option<Duration> {
+        let now = now_ms();
+        let pending = |remaining: Option<u64>, reset: Option<u64>| match (remaining, reset) {
+            (Some(0), Some(reset)) if reset > now => Some(reset - now),
+            _ => None,
+        };
+        let wait = pending(self.remaining_requests, self.requests_reset_ms)
+            .into_iter()
+            .chain(pending(self.remaining_tokens, self.tokens_reset_ms))
+            .max()?;
+        Some(Duration::from_millis(wait))
+    }
+
+    /// One-line summary for --verbose output, e.g.
+    /// "requests 499/500 (resets in 0.1s), tokens 29000/30000 (resets in 2.0s)".
+    pub fn summary(&self) -> String {
+        let now = now_ms();
+        let part = |name: &str, remaining: Option<u64>, limit: Option<u64>, reset: Option<u64>| {
+            let remaining = remaining?;
+            let mut s = match limit {
+                Some(limit) => format!("{name} {remaining}/{limit}"),
+                None => format!("{name} {remaining}"),
+            };
+            if let Some(reset) = reset.filter(|r| *r > now) {
+                s.push_str(&format!(" (resets in {:.1}s)", (reset - now) as f64 / 1000.0));
+            }
+            Some(s)
+        };
+        [
+            part("requests", self.remaining_requests, self.limit_requests, self.requests_reset_ms),
+            part("tokens", self.remaining_tokens, self.limit_tokens, self.tokens_reset_ms),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// All known quota states, keyed by "{provider}:{key fingerprint}".
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RateLimitStore {
+    #[serde(flatten)]
+    entries: HashMap<String, Quota>,
+}
+
+impl RateLimitStore {
+    fn store_path(config_dir: &Path) -> PathBuf {
+        config_dir.join("rate_limits.json")
+    }
+
+    /// Load from disk. Returns an empty store on any read or parse failure.
+    pub fn load(config_dir: &Path) -> Self {
+        std::fs::read_to_string(Self::store_path(config_dir))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes to a temporary file and renames it into place, so another
+    /// process never reads a half-written store.
+    pub fn save(&self, config_dir: &Path) -> Result<(), CrabError> {
+        std::fs::create_dir_all(config_dir)?;
+        let json = serde_json::to_string_pretty(self)?;
+        let path = Self::store_path(config_dir);
+        let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, provider: &str, key_id: &str) -> Option<&Quota> {
+        self.entries.get(&format!("{provider}:{key_id}"))
+    }
+
+    pub fn set(&mut self, provider: &str, key_id: &str, quota: Quota) {
+        self.entries.insert(format!("{provider}:{key_id}"), quota);
+    }
+}
+
+/// The store shared by this process, and the entries it changed.
+struct Shared {
+    store: RateLimitStore,
+    changed: HashSet<String>,
+}
+
+static SHARED: OnceLock<Mutex<Shared>> = OnceLock::new();
+
+fn shared() -> &'static Mutex<Shared> {
+    SHARED.get_or_init(|| {
+        Mutex::new(Shared {
+            store: RateLimitStore::load(&Config::config_dir()),
+            changed: HashSet::new(),
+        })
+    })
+}
+
+/// Returns the latest known quota for a provider key.
+pub fn quota(provider: &str, key_id: &str) -> Option<Quota> {
+    shared().lock().ok()?.store.get(provider, key_id).cloned()
+}
+
+/// Records the latest quota for a provider key. It is written to disk by
+/// `save`.
+pub fn update(provider: &str, key_id: &str, quota: Quota) {
+    if let Ok(mut shared) = shared().lock() {
+        shared.store.set(provider, key_id, quota);
+        shared.changed.insert(format!("{provider}:{key_id}"));
+    }
+}
+
+/// Writes the quota entries this process changed back to disk, on top of
+/// the current file so that other processes' updates are kept. An entry
+/// already updated more recently by another process is left alone.
+pub fn save() -> Result<(), CrabError> {
+    let Some(shared) = SHARED.get() else {
+        return Ok(());
+    };
+    let Ok(shared) = shared.lock() else {
+        return Ok(());
+    };
+    if shared.changed.is_empty() {
+        return Ok(());
+    }
+    let config_dir = Config::config_dir();
+    let mut store = RateLimitStore::load(&config_dir);
+    for entry in &shared.changed {
+        let Some(quota) = shared.store.entries.get(entry) else {
+            continue;
+        };
+        let newer = store
+            .entries
+            .get(entry)
+            .is_none_or(|existing| existing.updated_ms <= quota.updated_ms);
+        if newer {
+            store.entries.insert(entry.clone(), quota.clone());
+        }
+    }
+    store.save(&config_dir)
+}
+
+/// Quota observed during this process, by provider, for --verbose output.
+fn observed() -> &'static Mutex<HashMap<String, Quota>> {
+    static OBSERVED: OnceLock<Mutex<HashMap<String, Quota>>> = OnceLock::new();
+    OBSERVED.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Records the latest quota seen for a provider in this process.
+pub fn record_observed(provider: &str, quota: &Quota) {
+    if let Ok(mut map) = observed().lock() {
+        map.insert(provider.to_string(), quota.clone());
+    }
+}
+
+/// Returns the latest quota seen for a provider in this process, if any.
+pub fn last_observed(provider: &str) -> Option<Quota> {
+    observed().lock().ok()?.get(provider).cloned()
+}
+
+/// Short stable fingerprint of an API key (FNV-1a), so quota state can be
+/// kept per key without storing the key itself.
+pub fn key_fingerprint(key: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in key.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, names: &[&str]) -> Option<&'a str> {
+    names
+        .iter()
+        .find_map(|n| headers.get(*n))
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+}
+
+fn header_u64(headers: &HeaderMap, names: &[&str]) -> Option<u64> {
+    header_str(headers, names)?.parse::<f64>().ok().map(|v| v as u64)
+}
+
+/// Parses a reset header into a Unix timestamp in milliseconds. Accepts
+/// durations ("6m0s", "20ms", "1.5"), RFC 3339 timestamps (Anthropic),
+/// and epoch seconds or milliseconds (OpenRouter).
+fn header_reset(headers: &HeaderMap, names: &[&str], now: u64) -> Option<u64> {
+    let value = header_str(headers, names)?;
+    if let Ok(number) = value.parse::<f64>() {
+        return Some(match number {
+            n if n > 1e12 => n as u64,
+            n if n > 1e9 => (n * 1000.0) as u64,
+            n => now + (n * 1000.0) as u64,
+        });
+    }
+    if let Some(ms) = parse_duration_ms(value) {
+        return Some(now + ms);
+    }
+    parse_rfc3339_ms(value)
+}
+
+/// Parses Go-style durations such as "1h2m3.5s", "6m0s" or "120ms".
+fn parse_duration_ms(value: &str) -> Option<u64> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"(\d+(?:\.\d+)?)(ms|h|m|s)").expect("valid regex"));
+    let mut total = 0.0;
+    let mut matched = 0;
+    for caps in re.captures_iter(value) {
+        let amount: f64 = caps[1].parse().ok()?;
+        total += match &caps[2] {
+            "h" => amount * 3_600_000.0,
+            "m" => amount * 60_000.0,
+            "s" => amount * 1000.0,
+            _ => amount,
+        };
+        matched += caps[0].len();
+    }
+    (matched > 0 && matched == value.len()).then_some(total as u64)
+}
+
+/// Parses an RFC 3339 timestamp like "2024-05-01T12:00:30Z" or
+/// "2024-05-01T12:00:30.5+02:00" into Unix milliseconds.
+fn parse_rfc3339_ms(value: &str) -> Option<u64> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        Regex::new(
+            r"^(\d{4})-(\d{2})-(\d{2})[Tt ](\d{2}):(\d{2}):(\d{2})(\.\d+)?(?:[Zz]|([+-])(\d{2}):(\d{2}))$",
+        )
+        .expect("valid regex")
+    });
+    let caps = re.captures(value)?;
+    let num = |i: usize| caps[i].parse::<i64>().ok();
+
+    let (year, month, day) = (num(1)?, num(2)?, num(3)?);
+    // Days since the Unix epoch for a proleptic Gregorian date.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let mut secs = days * 86_400 + num(4)? * 3600 + num(5)? * 60 + num(6)?;
+    if let Some(sign) = caps.get(8) {
+        let offset = num(9)? * 3600 + num(10)? * 60;
+        secs -= if sign.as_str() == "+" { offset } else { -offset };
+    }
+    let frac_ms = caps
+        .get(7)
+        .and_then(|f| f.as_str().parse::<f64>().ok())
+        .map(|f| (f * 1000.0) as i64)
+        .unwrap_or(0);
+    u64::try_from(secs * 1000 + frac_ms).ok()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_714_564_800_000;
+
+    fn reset(value: &str) -> Option<u64> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-reset-tokens", value.parse().unwrap());
+        header_reset(&headers, RESET_TOKENS, NOW)
+    }
+
+    #[test]
+    fn parses_rfc3339_timestamps() {
+        assert_eq!(parse_rfc3339_ms("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_rfc3339_ms("2024-05-01T12:00:30Z"), Some(1_714_564_830_000));
+        assert_eq!(parse_rfc3339_ms("2024-02-29T00:00:00Z"), Some(1_709_164_800_000));
+        assert_eq!(parse_rfc3339_ms("2024-05-01 12:00:30z"), Some(1_714_564_830_000));
+    }
+
+    #[test]
+    fn parses_rfc3339_fractions_and_offsets() {
+        assert_eq!(parse_rfc3339_ms("2024-05-01T12:00:30.5Z"), Some(1_714_564_830_500));
+        assert_eq!(parse_rfc3339_ms("2024-05-01T12:00:30.123456Z"), Some(1_714_564_830_123));
+        assert_eq!(parse_rfc3339_ms("2024-05-01T12:00:30+02:00"), Some(1_714_557_630_000));
+        assert_eq!(parse_rfc3339_ms("2024-05-01T12:00:30.5-05:30"), Some(1_714_584_630_500));
+    }
+
+    #[test]
+    fn rejects_malformed_timestamps() {
+        assert_eq!(parse_rfc3339_ms("2024-05-01T12:00:30"), None);
+        assert_eq!(parse_rfc3339_ms("2024-05-01"), None);
+        assert_eq!(parse_rfc3339_ms("1969-12-31T23:59:59Z"), None);
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration_ms("250ms"), Some(250));
+        assert_eq!(parse_duration_ms("20s"), Some(20_000));
+        assert_eq!(parse_duration_ms("1.5s"), Some(1500));
+        assert_eq!(parse_duration_ms("1m30s"), Some(90_000));
+        assert_eq!(parse_duration_ms("6m0s"), Some(360_000));
+        assert_eq!(parse_duration_ms("1h2m3.5s"), Some(3_723_500));
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        assert_eq!(parse_duration_ms(""), None);
+        assert_eq!(parse_duration_ms("1m30x"), None);
+        assert_eq!(parse_duration_ms("soon"), None);
+    }
+
+    #[test]
+    fn reset_number_is_epoch_millis_epoch_seconds_or_relative_seconds() {
+        assert_eq!(reset("1714564830123"), Some(1_714_564_830_123));
+        assert_eq!(reset("1714564830"), Some(1_714_564_830_000));
+        assert_eq!(reset("20"), Some(NOW + 20_000));
+        assert_eq!(reset("1.5"), Some(NOW + 1500));
+    }
+
+    #[test]
+    fn reset_accepts_durations_and_timestamps() {
+        assert_eq!(reset("6m0s"), Some(NOW + 360_000));
+        assert_eq!(reset("120ms"), Some(NOW + 120));
+        assert_eq!(reset("2024-05-01T12:00:30Z"), Some(1_714_564_830_000));
+        assert_eq!(reset("later"), None);
+        assert_eq!(header_reset(&HeaderMap::new(), RESET_TOKENS, NOW), None);
+    }
+}
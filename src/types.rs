@@ -1,3 +1,4 @@
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
@@ -10,6 +11,21 @@ pub struct ModelInfo {
     pub supports_temperature: bool,
     /// Optional: The name of the parameter used for max tokens (e.g. "max_completion_tokens")
     pub max_tokens_param: Option<String>,
+    /// Optional: Inclusive (min, max) temperature range accepted by the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature_range: Option<(f32, f32)>,
+    /// Request parameters the model rejects; providers omit them from requests.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unsupported_params: Vec<String>,
+    /// Human-readable notes of constraints learned from provider errors.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub learned: Vec<String>,
+    /// Optional: Whether the model returns token log-probabilities (None if unknown).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supports_logprobs: Option<bool>,
+    /// Optional: Whether the model accepts image input (None if unknown).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supports_vision: Option<bool>,
 }
 
 impl ModelInfo {
@@ -19,10 +35,410 @@ impl ModelInfo {
             max_output_tokens: None,
             supports_temperature: true,
             max_tokens_param: None,
+            temperature_range: None,
+            unsupported_params: Vec::new(),
+            learned: Vec::new(),
+            supports_logprobs: None,
+            supports_vision: None,
         }
     }
 }
 
+impl ModelInfo {
+    /// Whether the model id names an embedding model
+    /// (text-embedding-3-small, mistral-embed, gemini-embedding-001, ...).
+    pub fn is_embedding_model(&self) -> bool {
+        self.id.contains("embed")
+    }
+}
+
+/// Full temperature range of most chat APIs.
+pub const DEFAULT_TEMPERATURE_RANGE: (f32, f32) = (0.0, 2.0);
+
+/// What a provider supports, for all of its models or for one of them.
+/// Requested options are checked against it before anything is sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    /// Accepts system role messages.
+    pub system_role: bool,
+    /// Can stream the answer as it is generated.
+    pub streaming: bool,
+    /// Accepts tool (function) definitions.
+    pub tools: bool,
+    /// Accepts image attachments.
+    pub vision: bool,
+    /// Accepts PDF attachments.
+    pub pdf: bool,
+    /// Can constrain the answer to a JSON schema.
+    pub json_schema: bool,
+    /// Reasons before answering (o-series, deepseek-reasoner, ...).
+    pub reasoning: bool,
+    /// Can continue a partial assistant message (ChatRequest::prefill).
+    pub prefill: bool,
+    /// Can return token log-probabilities (ChatRequest::logprobs).
+    pub logprobs: bool,
+    /// Can ground answers in a native web search (ChatRequest::web_search).
+    pub web_search: bool,
+    /// Inclusive (min, max) temperature range. None if the model does not
+    /// accept a temperature.
+    pub temperature: Option<(f32, f32)>,
+    /// Request parameter that carries the output token limit, when the
+    /// model needs another one than the provider's usual parameter.
+    pub max_tokens_param: Option<&'static str>,
+}
+
+impl Default for Capabilities {
+    /// Plain chat: user and assistant turns with a temperature, nothing else.
+    fn default() -> Self {
+        Self {
+            system_role: false,
+            streaming: false,
+            tools: false,
+            vision: false,
+            pdf: false,
+            json_schema: false,
+            reasoning: false,
+            prefill: false,
+            logprobs: false,
+            web_search: false,
+            temperature: Some(DEFAULT_TEMPERATURE_RANGE),
+            max_tokens_param: None,
+        }
+    }
+}
+
+impl Capabilities {
+    /// Narrows the capabilities with what the model cache learned about the
+    /// model from provider errors and model listings.
+    pub fn learned(mut self, info: Option<&ModelInfo>) -> Self {
+        let Some(info) = info else {
+            return self;
+        };
+        if !info.supports_temperature {
+            self.temperature = None;
+        } else if let Some(range) = info.temperature_range {
+            self.temperature = Some(range);
+        }
+        if let Some(logprobs) = info.supports_logprobs {
+            self.logprobs = logprobs;
+        }
+        if let Some(vision) = info.supports_vision {
+            self.vision = vision;
+            self.pdf &= vision;
+        }
+        self
+    }
+
+    /// The temperature to send for `requested`: clamped into the accepted
+    /// range, or None if the model does not accept one.
+    pub fn clamp_temperature(&self, requested: f32) -> Option<f32> {
+        self.temperature.map(|(min, max)| requested.clamp(min, max))
+    }
+
+    /// Names of the supported features, for display.
+    pub fn features(&self) -> Vec<&'static str> {
+        [
+            (self.system_role, "system-role"),
+            (self.streaming, "streaming"),
+            (self.tools, "tools"),
+            (self.vision, "vision"),
+            (self.pdf, "pdf"),
+            (self.json_schema, "json-schema"),
+            (self.reasoning, "reasoning"),
+            (self.prefill, "prefill"),
+            (self.logprobs, "logprobs"),
+            (self.web_search, "web-search"),
+        ]
+        .into_iter()
+        .filter_map(|(supported, name)| supported.then_some(name))
+        .collect()
+    }
+}
+
+/// Author of a chat message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+impl Role {
+    /// Role name used by OpenAI-compatible and Anthropic APIs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+/// A single message in a chat request: text, plus any attached files.
+#[derive(Debug, Clone)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+    /// Images and documents sent along with the text.
+    pub attachments: Vec<Attachment>,
+}
+
+impl ChatMessage {
+    pub fn user(content: &str) -> Self {
+        Self {
+            role: Role::User,
+            content: content.to_string(),
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.to_string(),
+            attachments: Vec::new(),
+        }
+    }
+}
+
+/// A file sent with a message: an image or a PDF document.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub file_name: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn is_pdf(&self) -> bool {
+        self.mime_type == "application/pdf"
+    }
+
+    /// The file contents, base64-encoded.
+    pub fn base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(&self.data)
+    }
+
+    /// The file as a `data:` URL, as OpenAI-compatible APIs expect it.
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type, self.base64())
+    }
+}
+
+/// A chat completion request, fully resolved by the CLI.
+/// Usually a single user message; continuation adds further turns.
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    /// None signals the provider to omit temperature from the request.
+    pub temperature: Option<f32>,
+    pub max_tokens: u32,
+    /// Name of the max tokens parameter, when the model needs a non-default one.
+    pub max_tokens_key: Option<String>,
+    /// Request parameters the model is known to reject; providers drop them.
+    pub omit_params: Vec<String>,
+    /// Start of the assistant's answer. Sent as a partial assistant turn
+    /// that the model continues from; not included in the returned text.
+    pub prefill: Option<String>,
+    /// Request token log-probabilities with this many alternatives per
+    /// token (0 for the chosen tokens only). None leaves them off.
+    pub logprobs: Option<u8>,
+    /// Let the model search the web and ground its answer in the results,
+    /// using the provider's native search feature.
+    pub web_search: bool,
+}
+
+/// A fill-in-the-middle code completion request: the model writes the code
+/// that goes between `prefix` and `suffix`.
+#[derive(Debug, Clone)]
+pub struct FimRequest {
+    pub model: String,
+    pub prefix: String,
+    pub suffix: String,
+    /// None signals the provider to omit temperature from the request.
+    pub temperature: Option<f32>,
+    pub max_tokens: u32,
+}
+
+/// A batch of texts to turn into embedding vectors.
+#[derive(Debug, Clone)]
+pub struct EmbedRequest {
+    pub model: String,
+    pub inputs: Vec<String>,
+    /// Requested vector size, for models that can shorten their output.
+    pub dimensions: Option<u32>,
+}
+
+/// An audio file to transcribe into text.
+#[derive(Debug, Clone)]
+pub struct TranscriptionRequest {
+    /// None lets the provider pick its default transcription model.
+    pub model: Option<String>,
+    pub file_name: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+/// A request to generate images from a text prompt.
+#[derive(Debug, Clone)]
+pub struct ImageRequest {
+    pub model: String,
+    pub prompt: String,
+    /// "WIDTHxHEIGHT" or, for Imagen, an aspect ratio such as "16:9".
+    pub size: Option<String>,
+    pub quality: Option<String>,
+    pub count: u32,
+}
+
+/// Sizes, qualities and image counts a model accepts.
+#[derive(Debug, Clone)]
+pub struct ImageOptions {
+    /// Accepted sizes; empty means the model has no size option.
+    pub sizes: &'static [&'static str],
+    /// The model takes any "WIDTHxHEIGHT" instead of a fixed list.
+    pub any_size: bool,
+    /// Accepted qualities; empty means the model has no quality option.
+    pub qualities: &'static [&'static str],
+    pub max_count: u32,
+}
+
+/// A generated image: the encoded file (PNG, JPEG, ...).
+#[derive(Debug, Clone)]
+pub struct GeneratedImage(pub Vec<u8>);
+
+/// A request to read text aloud.
+#[derive(Debug, Clone)]
+pub struct SpeechRequest {
+    pub model: String,
+    pub input: String,
+    /// None lets the provider pick its default voice.
+    pub voice: Option<String>,
+    /// Audio container, e.g. "mp3" or "wav". None uses the provider default.
+    pub format: Option<String>,
+    pub speed: Option<f32>,
+}
+
+/// Why the model stopped generating.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinishReason {
+    /// Natural end of the answer or a stop sequence.
+    Stop,
+    /// The output token limit was reached; the answer is truncated.
+    Length,
+    /// The provider's safety system cut the answer short.
+    ContentFilter,
+    /// Any other provider-specific reason, verbatim.
+    Other(String),
+}
+
+impl FinishReason {
+    /// Maps the provider-specific reason strings onto the common variants
+    /// (OpenAI "length", Anthropic "max_tokens", Gemini "MAX_TOKENS", ...).
+    pub fn parse(raw: &str) -> Self {
+        match raw.to_lowercase().as_str() {
+            "stop" | "end_turn" | "stop_sequence" | "eos" => FinishReason::Stop,
+            "length" | "max_tokens" | "max_output_tokens" | "model_length" => {
+                FinishReason::Length
+            }
+            "content_filter" | "safety" | "refusal" | "recitation" | "blocklist"
+            | "prohibited_content" | "spii" | "image_safety" => FinishReason::ContentFilter,
+            _ => FinishReason::Other(raw.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FinishReason::Stop => f.write_str("stop"),
+            FinishReason::Length => f.write_str("length"),
+            FinishReason::ContentFilter => f.write_str("content_filter"),
+            FinishReason::Other(raw) => f.write_str(raw),
+        }
+    }
+}
+
+/// Text answer returned by a provider, with generation metadata.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub text: String,
+    pub finish_reason: Option<FinishReason>,
+    /// Number of output tokens reported by the provider.
+    pub output_tokens: Option<u32>,
+    /// Per-token log-probabilities, when requested and returned.
+    pub logprobs: Option<Vec<TokenLogprob>>,
+    /// Web pages the answer is grounded in, when web search was enabled.
+    pub sources: Vec<Source>,
+    /// The provider's id of the generation, when reported.
+    pub id: Option<String>,
+    /// Upstream provider that served the request, for routers such as
+    /// OpenRouter.
+    pub upstream_provider: Option<String>,
+}
+
+impl Completion {
+    /// True if the provider reports the answer was cut off by the token limit.
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason == Some(FinishReason::Length)
+    }
+
+    /// Adds sources, skipping URLs that are already listed.
+    pub fn add_sources(&mut self, sources: impl IntoIterator<Item = Source>) {
+        for source in sources {
+            if !self.sources.iter().any(|s| s.url == source.url) {
+                self.sources.push(source);
+            }
+        }
+    }
+}
+
+/// A web page cited by a grounded answer.
+#[derive(Debug, Clone, Serialize)]
+pub struct Source {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// Progress of an asynchronous batch job, as reported by the provider.
+#[derive(Debug, Clone)]
+pub struct BatchStatus {
+    pub id: String,
+    /// Provider status string, e.g. "in_progress", "completed" or "ended".
+    pub status: String,
+    /// True once the job has stopped processing and results can be fetched.
+    pub finished: bool,
+    pub total: u32,
+    pub succeeded: u32,
+    pub failed: u32,
+}
+
+/// Outcome of one request of a batch job. `index` is the request's
+/// position in the submitted list.
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    pub index: usize,
+    /// The answer, or the provider's error message for this request.
+    pub outcome: Result<Completion, String>,
+}
+
+/// Log-probability of one generated token.
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    /// Natural log of the token's probability.
+    pub logprob: f64,
+    /// Most likely alternatives at this position, most likely first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// A candidate token considered at one position of the answer.
+#[derive(Debug, Clone, Serialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+}
+
 /// Enumeration of all supported LLM providers.
 /// Used for compile-time validation and case-insensitive string parsing.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -35,11 +451,14 @@ pub enum ProviderName {
     Together,
     Mistral,
     DeepSeek,
+    /// Built-in offline provider for scripting and tests.
+    Mock,
 }
 
 impl ProviderName {
     /// Complete list of all supported providers.
     /// Used for iteration in --list-providers and --list-models -a.
+    /// The offline `mock` provider is not listed.
     pub const ALL: &'static [ProviderName] = &[
         ProviderName::OpenAI,
         ProviderName::Anthropic,
@@ -62,6 +481,7 @@ impl ProviderName {
             ProviderName::Together => "together",
             ProviderName::Mistral => "mistral",
             ProviderName::DeepSeek => "deepseek",
+            ProviderName::Mock => "mock",
         }
     }
 }
@@ -85,6 +505,7 @@ impl FromStr for ProviderName {
             "together" => Ok(ProviderName::Together),
             "mistral" => Ok(ProviderName::Mistral),
             "deepseek" => Ok(ProviderName::DeepSeek),
+            "mock" => Ok(ProviderName::Mock),
             _ => Err(format!("Unknown provider: {s}")),
         }
     }